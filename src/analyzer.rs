@@ -0,0 +1,372 @@
+use std::collections::{HashMap, VecDeque};
+
+use egui::{Align, Align2, DragValue, FontId, Layout, Pos2, Rect, Sense, Shape, Stroke, Vec2};
+
+use crate::{
+    dag::DeviceId,
+    devices::{Signal, SignalKind},
+    drawing_utils::{color_to_color32, ColorPalette},
+};
+
+// history is kept for this many beats, however much of it is shown
+const MAX_HISTORY_BEATS: f32 = 64.0;
+
+const ROW_HEIGHT: f32 = 28.0;
+const HEADER_HEIGHT: f32 = 16.0;
+const NAME_WIDTH: f32 = 140.0;
+
+/// Output history of one probed device
+struct Trace {
+    device: DeviceId,
+    name: String,
+    kind: SignalKind,
+
+    // beat each change in output happened at, and the new output
+    changes: VecDeque<(f32, Signal)>,
+}
+
+impl Trace {
+    fn record(&mut self, beat: f32, value: Signal) {
+        if self.changes.back().is_some_and(|(_, last)| *last == value) {
+            return;
+        }
+        self.changes.push_back((beat, value));
+
+        // the change before the oldest kept beat is still needed, since the
+        // output stays at its value until the next change
+        while self
+            .changes
+            .get(1)
+            .is_some_and(|(b, _)| *b < beat - MAX_HISTORY_BEATS)
+        {
+            self.changes.pop_front();
+        }
+    }
+
+    /// Start and end of the stretch of unchanging output around `beat`, and
+    /// the output during it. The end is None if the output hasn't changed since.
+    fn run_at(&self, beat: f32) -> Option<(f32, Option<f32>, Signal)> {
+        let i = self.changes.iter().rposition(|(b, _)| *b <= beat)?;
+        let (start, value) = self.changes[i];
+        let end = self.changes.get(i + 1).map(|(b, _)| *b);
+        Some((start, end, value))
+    }
+}
+
+/// Records the outputs of probed devices and draws them as stacked waveforms
+/// on a beat grid, like a logic analyzer
+pub struct Analyzer {
+    traces: Vec<Trace>,
+
+    // beat of the latest recording
+    now: f32,
+
+    // how many beats are shown at once
+    window: f32,
+
+    // nothing is recorded while frozen, so the history can be looked through
+    frozen: bool,
+
+    // beat at the right edge of the view, None to follow the latest recording
+    view_end: Option<f32>,
+
+    // beat the measurement cursor was put at by clicking
+    cursor: Option<f32>,
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        Analyzer {
+            traces: Vec::new(),
+            now: 0.0,
+            window: 8.0,
+            frozen: false,
+            view_end: None,
+            cursor: None,
+        }
+    }
+
+    pub fn is_probed(&self, device: DeviceId) -> bool {
+        self.traces.iter().any(|t| t.device == device)
+    }
+
+    pub fn probe(&mut self, device: DeviceId, name: String, kind: SignalKind) {
+        if !self.is_probed(device) {
+            self.traces.push(Trace {
+                device,
+                name,
+                kind,
+                changes: VecDeque::new(),
+            });
+        }
+    }
+
+    /// Stops showing devices that have been deleted
+    pub fn forget_missing(&mut self, exists: impl Fn(DeviceId) -> bool) {
+        self.traces.retain(|t| exists(t.device));
+    }
+
+    pub fn record(&mut self, beat: f32, outputs: &HashMap<DeviceId, Signal>) {
+        if self.frozen {
+            return;
+        }
+
+        // the beat clock only goes backwards when the session is reset
+        if beat < self.now {
+            for trace in self.traces.iter_mut() {
+                trace.changes.clear();
+            }
+            self.cursor = None;
+        }
+        self.now = beat;
+
+        for trace in self.traces.iter_mut() {
+            if let Some(value) = outputs.get(&trace.device) {
+                trace.record(beat, *value);
+            }
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, bpm: u32, colors: &ColorPalette) {
+        ui.horizontal(|ui| {
+            if ui.toggle_value(&mut self.frozen, "Freeze").changed() && !self.frozen {
+                self.view_end = None;
+            }
+            if self.view_end.is_some() && ui.button("Live").clicked() {
+                self.view_end = None;
+            }
+
+            ui.separator();
+
+            ui.label("Beats");
+            ui.add(DragValue::new(&mut self.window).range(1.0..=MAX_HISTORY_BEATS));
+
+            ui.separator();
+
+            if ui.button("Clear").clicked() {
+                self.traces.clear();
+                self.cursor = None;
+            }
+
+            if self.traces.is_empty() {
+                ui.label("Select devices and probe them to record their outputs");
+            } else {
+                ui.label("Drag to scroll back, click to measure");
+            }
+        });
+
+        ui.horizontal_top(|ui| {
+            ui.vertical(|ui| {
+                ui.spacing_mut().item_spacing.y = 0.0;
+                ui.add_space(HEADER_HEIGHT);
+
+                let mut removed = None;
+                for (i, trace) in self.traces.iter().enumerate() {
+                    ui.allocate_ui_with_layout(
+                        Vec2::new(NAME_WIDTH, ROW_HEIGHT),
+                        Layout::left_to_right(Align::Center),
+                        |ui| {
+                            if ui.small_button("✖").clicked() {
+                                removed = Some(i);
+                            }
+                            ui.label(&trace.name);
+                        },
+                    );
+                }
+
+                if let Some(i) = removed {
+                    self.traces.remove(i);
+                }
+            });
+
+            self.plot_ui(ui, bpm, colors);
+        });
+    }
+
+    fn plot_ui(&mut self, ui: &mut egui::Ui, bpm: u32, colors: &ColorPalette) {
+        let size = Vec2::new(
+            ui.available_width(),
+            HEADER_HEIGHT + ROW_HEIGHT * self.traces.len() as f32,
+        );
+        let (response, painter) = ui.allocate_painter(size, Sense::click_and_drag());
+        let rect = response.rect;
+
+        let end = self.view_end.unwrap_or(self.now);
+        let start = end - self.window;
+        let x_at = |beat: f32| rect.left() + (beat - start) / self.window * rect.width();
+        let beat_at = |x: f32| start + (x - rect.left()) / rect.width() * self.window;
+
+        if response.dragged() {
+            let earliest = self
+                .traces
+                .iter()
+                .filter_map(|t| t.changes.front().map(|(b, _)| *b))
+                .fold(self.now, f32::min);
+            let delta = response.drag_delta().x / rect.width() * self.window;
+            let latest_start = (earliest + self.window).min(self.now);
+            self.view_end = Some((end - delta).clamp(latest_start, self.now));
+        } else if response.clicked() {
+            self.cursor = response.interact_pointer_pos().map(|pos| beat_at(pos.x));
+        }
+
+        let grid = color_to_color32(colors.bg_3);
+        let label = color_to_color32(colors.fg_3);
+        let low = color_to_color32(colors.fg_2);
+        let high = color_to_color32(colors.active);
+
+        painter.rect_filled(rect, 0.0, color_to_color32(colors.bg_0));
+
+        // a line for every beat, labeled at the start of every bar
+        for beat in start.ceil() as i32..=end.floor() as i32 {
+            let x = x_at(beat as f32);
+            painter.line_segment(
+                [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+                Stroke::new(1.0, grid),
+            );
+            if beat % 4 == 0 {
+                painter.text(
+                    Pos2::new(x + 2.0, rect.top()),
+                    Align2::LEFT_TOP,
+                    beat.to_string(),
+                    FontId::proportional(11.0),
+                    label,
+                );
+            }
+        }
+
+        for (i, trace) in self.traces.iter().enumerate() {
+            let top = rect.top() + HEADER_HEIGHT + i as f32 * ROW_HEIGHT + 4.0;
+            let bottom = top + ROW_HEIGHT - 8.0;
+
+            // numbers are drawn as steps between the smallest and largest value
+            let (min, max) = trace
+                .changes
+                .iter()
+                .map(|(_, v)| v.as_number())
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| {
+                    (lo.min(v), hi.max(v))
+                });
+            let y_of = |value: Signal| match trace.kind {
+                SignalKind::Bool if value.as_bool() => top,
+                SignalKind::Bool => bottom,
+                SignalKind::Number if max > min => {
+                    bottom - (value.as_number() - min) / (max - min) * (bottom - top)
+                }
+                SignalKind::Number => (top + bottom) / 2.0,
+            };
+
+            let mut prev_y = None;
+            for (j, (from, value)) in trace.changes.iter().enumerate() {
+                let to = trace.changes.get(j + 1).map_or(self.now, |(b, _)| *b);
+                if to < start || *from > end {
+                    prev_y = Some(y_of(*value));
+                    continue;
+                }
+
+                let left = x_at(from.max(start));
+                let right = x_at(to.min(end));
+                let y = y_of(*value);
+                let on = trace.kind == SignalKind::Bool && value.as_bool();
+                let color = if on { high } else { low };
+
+                if on {
+                    painter.rect_filled(
+                        Rect::from_min_max(Pos2::new(left, top), Pos2::new(right, bottom)),
+                        0.0,
+                        high.gamma_multiply(0.2),
+                    );
+                }
+                if let Some(prev_y) = prev_y.filter(|_| *from >= start) {
+                    painter.line_segment(
+                        [Pos2::new(left, prev_y), Pos2::new(left, y)],
+                        Stroke::new(1.0, low),
+                    );
+                }
+                painter.line_segment(
+                    [Pos2::new(left, y), Pos2::new(right, y)],
+                    Stroke::new(1.5, color),
+                );
+
+                if trace.kind == SignalKind::Number && right - left > 24.0 {
+                    painter.text(
+                        Pos2::new(left + 2.0, top),
+                        Align2::LEFT_TOP,
+                        format!("{}", value.as_number().round()),
+                        FontId::proportional(10.0),
+                        label,
+                    );
+                }
+
+                prev_y = Some(y);
+            }
+        }
+
+        if let Some(pos) = response.hover_pos() {
+            painter.line_segment(
+                [
+                    Pos2::new(pos.x, rect.top()),
+                    Pos2::new(pos.x, rect.bottom()),
+                ],
+                Stroke::new(1.0, label),
+            );
+        }
+
+        if let Some(cursor) = self.cursor.filter(|c| (start..=end).contains(c)) {
+            self.draw_measurements(&painter, rect, x_at(cursor), cursor, bpm, colors);
+        }
+    }
+
+    // marks the cursor and labels each trace with how long its output had
+    // been at the value under the cursor
+    fn draw_measurements(
+        &self,
+        painter: &egui::Painter,
+        rect: Rect,
+        x: f32,
+        cursor: f32,
+        bpm: u32,
+        colors: &ColorPalette,
+    ) {
+        let color = color_to_color32(colors.fg_0);
+        painter.line_segment(
+            [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+            Stroke::new(1.0, color),
+        );
+        painter.text(
+            Pos2::new(x + 2.0, rect.top()),
+            Align2::LEFT_TOP,
+            format!("{:.2}", cursor),
+            FontId::proportional(11.0),
+            color,
+        );
+
+        for (i, trace) in self.traces.iter().enumerate() {
+            let Some((from, to, value)) = trace.run_at(cursor) else {
+                continue;
+            };
+            let beats = to.unwrap_or(self.now) - from;
+            let ms = beats * 60_000.0 / bpm as f32;
+            let text = match trace.kind {
+                SignalKind::Bool => format!(
+                    "{} for {:.2} beats / {:.0} ms",
+                    if value.as_bool() { "on" } else { "off" },
+                    beats,
+                    ms
+                ),
+                SignalKind::Number => format!("{} for {:.2} beats", value.as_number(), beats),
+            };
+
+            let y = rect.top() + HEADER_HEIGHT + (i as f32 + 0.5) * ROW_HEIGHT;
+            let galley = painter.layout_no_wrap(text, FontId::proportional(11.0), color);
+            let text_rect = Align2::LEFT_CENTER
+                .anchor_size(Pos2::new(x + 4.0, y), galley.size())
+                .expand(2.0);
+            painter.rect_filled(text_rect, 0.0, color_to_color32(colors.bg_1));
+            painter.add(Shape::galley(
+                text_rect.min + Vec2::splat(2.0),
+                galley,
+                color,
+            ));
+        }
+    }
+}
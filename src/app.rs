@@ -1,23 +1,40 @@
 use core::panic;
 
-use egui::{menu, style::WidgetVisuals, style::Widgets, Align2, CornerRadius, Stroke, Visuals};
+use egui::{
+    menu, style::WidgetVisuals, style::Widgets, Align, Align2, CornerRadius, DragValue, FontId,
+    Layout, RichText, Stroke, Visuals,
+};
 use macroquad::{
     input::{
         is_key_down, is_key_pressed, is_mouse_button_pressed, is_mouse_button_released,
-        mouse_position, KeyCode, MouseButton,
+        mouse_position, mouse_wheel, KeyCode, MouseButton,
     },
     math::{vec2, Rect, Vec2},
+    miniquad::window::{clipboard_get, clipboard_set},
     shapes::draw_rectangle_lines,
-    window::clear_background,
+    time::get_time,
+    window::{clear_background, screen_height, screen_width},
 };
 
 use crate::{
+    analyzer::Analyzer,
+    comment::Comment,
+    config::{AppConfig, MAX_BPM, MIN_BPM},
     dag::{DeviceId, WireType},
-    devices::{clock::Clock, gate::Gate, latch::Latch, note::Note, trigger::Trigger},
+    devices::{
+        clock::Clock, counter::Counter, debounce::Debounce, delay::Delay, edge::Edge, gate::Gate,
+        latch::Latch, note::Note, quantizer::Quantizer, ratchet::Ratchet, sample_hold::SampleHold,
+        trigger::Trigger, SignalKind,
+    },
     drawing_utils::{
-        color_to_color32, draw_wire_between_devices, draw_wire_from_device, ColorPalette,
+        color_to_color32, draw_wire_between_devices, draw_wire_from_device, ColorPalette, Theme,
     },
-    midi::MidiConfig,
+    library,
+    midi::{MidiConfig, MidiError, MidiInputConfig, Transpose},
+    midi_learn::{learn_menu, take_parameter_request, ControlSource, LearnAction, LearnTarget},
+    midi_monitor::MidiMonitor,
+    minimap::Minimap,
+    patch::{is_patch, read_patch, write_patch},
     session::Session,
 };
 
@@ -29,13 +46,42 @@ enum CursorState {
     DraggingInvalidWire(DeviceId, WireType),
     DraggingSelectBox(Vec2),
     PanningViewport(Vec2),
+    NavigatingMinimap,
+}
+
+// a wire dropped on a device with more than one aux input it could go into,
+// waiting for the user to pick one
+struct PortChoice {
+    from: DeviceId,
+    to: DeviceId,
+    wire_type: WireType,
+    feedback: bool,
+    ports: Vec<usize>,
+
+    // where on screen the ports are listed
+    position: Vec2,
 }
 
 const INSPECTOR_WIDTH: f32 = 200.0;
 
+// max seconds between the two clicks of a double-click
+const DOUBLE_CLICK_TIME: f64 = 0.3;
+
+const MIN_SCALE: f32 = 0.25;
+const MAX_SCALE: f32 = 4.0;
+
+// how much one step of the mouse wheel zooms in or out
+const ZOOM_STEP: f32 = 1.1;
+
+// world space left around devices when framing them
+const FRAME_PADDING: f32 = 48.0;
+
 pub struct DrawContext {
     pub colors: ColorPalette,
     pub viewport_offset: Vec2,
+
+    // how many pixels a unit of world space takes up on screen
+    pub scale: f32,
     pub egui_visuals: Visuals,
 }
 
@@ -107,16 +153,54 @@ impl DrawContext {
         DrawContext {
             colors,
             viewport_offset: Vec2::ZERO,
+            scale: 1.0,
             egui_visuals: visuals,
         }
     }
 
+    pub fn set_palette(&mut self, colors: ColorPalette) {
+        *self = DrawContext {
+            viewport_offset: self.viewport_offset,
+            scale: self.scale,
+            ..DrawContext::new(colors)
+        };
+    }
+
     pub fn world_to_viewport(&self, world_coords: Vec2) -> Vec2 {
-        world_coords + self.viewport_offset
+        world_coords * self.scale + self.viewport_offset
     }
 
     pub fn viewport_to_world(&self, viewport_coords: Vec2) -> Vec2 {
-        viewport_coords - self.viewport_offset
+        (viewport_coords - self.viewport_offset) / self.scale
+    }
+
+    /// Part of the world shown on the canvas
+    pub fn view_rect(&self, canvas: Rect) -> Rect {
+        let top_left = self.viewport_to_world(canvas.point());
+        let size = canvas.size() / self.scale;
+        Rect::new(top_left.x, top_left.y, size.x, size.y)
+    }
+
+    /// Moves the view so `world_coords` is in the middle of the canvas
+    pub fn center_on(&mut self, world_coords: Vec2, canvas: Rect) {
+        self.viewport_offset = canvas.center() - world_coords * self.scale;
+    }
+
+    /// Fits `area` of the world into the canvas, without zooming in past
+    /// the normal size
+    pub fn frame(&mut self, area: Rect, canvas: Rect) {
+        let padded = Vec2::new(area.w, area.h) + 2.0 * FRAME_PADDING;
+        let fit = canvas.size() / padded;
+        self.scale = fit.x.min(fit.y).clamp(MIN_SCALE, 1.0);
+        self.center_on(area.center(), canvas);
+    }
+
+    /// Changes the scale while keeping the world point under `anchor` (in
+    /// viewport coordinates) in place
+    pub fn zoom_around(&mut self, anchor: Vec2, scale: f32) {
+        let world_anchor = self.viewport_to_world(anchor);
+        self.scale = scale.clamp(MIN_SCALE, MAX_SCALE);
+        self.viewport_offset = anchor - world_anchor * self.scale;
     }
 }
 
@@ -126,29 +210,141 @@ pub struct App {
     draw_ctx: DrawContext,
 
     context_menu: Option<Vec2>,
+    port_choice: Option<PortChoice>,
+
+    // device that was last clicked on and when, for detecting double-clicks
+    last_click: Option<(DeviceId, f64)>,
+
+    library_entries: Vec<String>,
+
+    // patch this instance last put on the system clipboard. The session's
+    // own clipboard is pasted instead of it, since it may have changed since.
+    copied_patch: Option<String>,
+
+    // name being typed in while saving the selection to the library
+    library_save_name: Option<String>,
+    patch_error: Option<String>,
+
+    egui_wants_keyboard: bool,
+
+    // selected devices and comments from before the select box was started,
+    // kept when adding to them
+    box_select_base: (Vec<DeviceId>, Vec<usize>),
+
+    // part of the screen not covered by panels, where the patch is drawn
+    canvas: Rect,
+    show_minimap: bool,
+
+    analyzer: Analyzer,
+    show_analyzer: bool,
+
+    midi_monitor: MidiMonitor,
+    show_midi_monitor: bool,
 
     midi_config: MidiConfig,
+    midi_input: MidiInputConfig,
+    midi_error: Option<MidiError>,
+
+    // what the next moved MIDI control will get mapped to
+    learning: Option<LearnTarget>,
+
+    config: AppConfig,
 }
 
 impl App {
-    pub fn new(colors: ColorPalette) -> Self {
+    pub fn new(config: AppConfig) -> Self {
+        let mut midi_config = MidiConfig::new(config.output_ports.clone());
+        let midi_error = midi_config
+            .refresh_ports()
+            .and_then(|_| midi_config.connect_wanted_ports())
+            .err();
+
+        #[cfg(unix)]
+        let midi_error = if config.virtual_port {
+            midi_config.open_virtual_port().err().or(midi_error)
+        } else {
+            midi_error
+        };
+
+        let mut midi_input = MidiInputConfig::new(config.input_ports.clone());
+        let midi_error = midi_input
+            .refresh_ports()
+            .and_then(|_| midi_input.connect_wanted_ports())
+            .err()
+            .or(midi_error);
+
+        let mut session = Session::new();
+        session.update_ctx.bpm = config.default_bpm;
+
         App {
-            session: Session::new(),
+            session,
             cursor: CursorState::Idle,
-            draw_ctx: DrawContext::new(colors),
+            draw_ctx: DrawContext::new(config.theme.palette()),
             context_menu: None,
-            midi_config: MidiConfig::new(),
+            port_choice: None,
+            last_click: None,
+            library_entries: Vec::new(),
+            copied_patch: None,
+            library_save_name: None,
+            patch_error: None,
+
+            egui_wants_keyboard: false,
+
+            box_select_base: (Vec::new(), Vec::new()),
+
+            canvas: Rect::new(0.0, 0.0, screen_width(), screen_height()),
+            show_minimap: true,
+
+            analyzer: Analyzer::new(),
+            show_analyzer: false,
+
+            midi_monitor: MidiMonitor::new(),
+            show_midi_monitor: false,
+            midi_config,
+            midi_input,
+            midi_error,
+            learning: None,
+            config,
         }
     }
 
     pub fn handle_inputs(&mut self) {
+        // the save dialog takes the keyboard, so don't delete or pause on
+        // keys typed into it
+        if self.library_save_name.is_some() {
+            return;
+        }
+
         let (mx, my) = mouse_position();
         let m_pos = vec2(mx, my);
         let device_under_mouse = self
             .session
             .get_device_at(self.draw_ctx.viewport_to_world(m_pos));
+        let feedback = feedback_key_down();
+
+        let (_, wheel) = mouse_wheel();
+        if wheel != 0.0 {
+            let scale = self.draw_ctx.scale * ZOOM_STEP.powf(wheel.signum());
+            self.draw_ctx.zoom_around(m_pos, scale);
+        }
+
+        let over_minimap = self.minimap().is_some_and(|m| m.rect.contains(m_pos));
+
+        // clicking anywhere on the canvas drops a wire waiting for its port
+        if [MouseButton::Left, MouseButton::Right, MouseButton::Middle]
+            .into_iter()
+            .any(is_mouse_button_pressed)
+        {
+            self.port_choice = None;
+        }
 
         match self.cursor {
+            CursorState::Idle if over_minimap => {
+                if is_mouse_button_pressed(MouseButton::Left) {
+                    self.cursor = CursorState::NavigatingMinimap;
+                }
+            }
+
             CursorState::Idle => {
                 if is_mouse_button_pressed(MouseButton::Middle) {
                     self.cursor = CursorState::PanningViewport(m_pos);
@@ -157,19 +353,31 @@ impl App {
                 match device_under_mouse {
                     Some(id) => {
                         if is_mouse_button_pressed(MouseButton::Left) {
-                            if !self.session.selected.contains(&id) {
-                                self.session.clear_selection();
-                                self.session.select_device(id);
+                            let now = get_time();
+                            let double_clicked = self.last_click.is_some_and(|(last_id, time)| {
+                                last_id == id && now - time < DOUBLE_CLICK_TIME
+                            });
+
+                            if double_clicked && self.session.open_subpatch(id) {
+                                self.last_click = None;
+                            } else if shift_key_down() {
+                                self.session.toggle_device_selection(id);
+                                self.last_click = None;
+                            } else {
+                                if !self.session.selected.contains(&id) {
+                                    self.session.clear_selection();
+                                    self.session.select_device(id);
+                                }
+                                self.last_click = Some((id, now));
+                                self.cursor = CursorState::DraggingSelectedDevices(m_pos);
                             }
-                            self.cursor = CursorState::DraggingSelectedDevices(m_pos);
                         }
 
                         if is_mouse_button_pressed(MouseButton::Right) {
                             let dev = self.session.devices.get(&id).unwrap();
                             if dev.has_output() {
-                                if is_key_down(KeyCode::LeftShift)
-                                    || is_key_down(KeyCode::RightShift)
-                                {
+                                // only boolean signals can be negated
+                                if shift_key_down() && dev.output_kind() == SignalKind::Bool {
                                     self.cursor =
                                         CursorState::DraggingLooseWire(id, WireType::Negated);
                                 } else {
@@ -181,30 +389,62 @@ impl App {
                     }
                     None => {
                         if is_mouse_button_pressed(MouseButton::Right) {
-                            let wire_under_mouse = self
-                                .session
-                                .get_wire_at(self.draw_ctx.viewport_to_world(m_pos));
+                            let wire_under_mouse = self.session.get_wire_at(
+                                self.draw_ctx.viewport_to_world(m_pos),
+                                self.draw_ctx.scale,
+                            );
 
                             match wire_under_mouse {
                                 Some(edge) => {
-                                    self.session.disconnect_devices(edge.from, edge.to);
+                                    self.session
+                                        .disconnect_devices(edge.from, edge.to, edge.port);
                                     self.cursor =
                                         CursorState::DraggingLooseWire(edge.from, edge.wire_type);
                                 }
                                 None => {
                                     self.context_menu = Some(m_pos);
+                                    self.library_entries = library::entries();
                                 }
                             }
                         }
+                        let comment_under_mouse = self
+                            .session
+                            .get_comment_at(self.draw_ctx.viewport_to_world(m_pos));
+
                         if is_mouse_button_pressed(MouseButton::Left) {
-                            self.cursor = CursorState::DraggingSelectBox(m_pos);
+                            match comment_under_mouse {
+                                Some(i) if shift_key_down() => {
+                                    self.session.toggle_comment_selection(i);
+                                }
+                                Some(i) => {
+                                    if !self.session.comments[i].selected {
+                                        self.session.clear_selection();
+                                        self.session.select_comment(i);
+                                    }
+                                    self.cursor = CursorState::DraggingSelectedDevices(m_pos);
+                                }
+                                None => {
+                                    // holding shift adds to the selection
+                                    // instead of replacing it
+                                    self.box_select_base = if shift_key_down() {
+                                        (
+                                            self.session.selected.clone(),
+                                            self.session.selected_comments(),
+                                        )
+                                    } else {
+                                        (Vec::new(), Vec::new())
+                                    };
+                                    self.cursor = CursorState::DraggingSelectBox(m_pos);
+                                }
+                            }
                         }
                     }
                 }
             }
 
             CursorState::DraggingSelectedDevices(from) => {
-                self.session.move_selected_devices(m_pos - from);
+                self.session
+                    .move_selected_devices((m_pos - from) / self.draw_ctx.scale);
                 self.cursor = CursorState::DraggingSelectedDevices(m_pos);
 
                 if is_mouse_button_released(MouseButton::Left) {
@@ -217,7 +457,7 @@ impl App {
                 if is_mouse_button_released(MouseButton::Right) {
                     self.cursor = CursorState::Idle;
                 } else if let Some(to_id) = device_under_mouse {
-                    if self.session.can_connect(from_id, to_id) {
+                    if self.session.can_connect(from_id, to_id, feedback) {
                         self.cursor = CursorState::DraggingConnectedWire(from_id, to_id, wire_type);
                     } else {
                         self.cursor = CursorState::DraggingInvalidWire(from_id, wire_type);
@@ -227,12 +467,32 @@ impl App {
 
             CursorState::DraggingConnectedWire(from_id, to_id, wire_type) => {
                 if is_mouse_button_released(MouseButton::Right) {
-                    self.session.connect_devices(from_id, to_id, wire_type);
+                    let ports = self.session.free_input_ports(from_id, to_id, feedback);
+                    let feedback = feedback || self.session.closes_loop(from_id, to_id);
+                    match ports[..] {
+                        [] => {}
+                        // the main input is what a wire is usually meant for,
+                        // only ask when it isn't free
+                        [port, ..] if port == 0 || ports.len() == 1 => {
+                            self.session
+                                .connect_devices(from_id, to_id, wire_type, port, feedback);
+                        }
+                        _ => {
+                            self.port_choice = Some(PortChoice {
+                                from: from_id,
+                                to: to_id,
+                                wire_type,
+                                feedback,
+                                ports,
+                                position: m_pos,
+                            });
+                        }
+                    }
                     self.cursor = CursorState::Idle;
                 } else {
                     match device_under_mouse {
                         Some(to_id) => {
-                            if !self.session.can_connect(from_id, to_id) {
+                            if !self.session.can_connect(from_id, to_id, feedback) {
                                 self.cursor = CursorState::DraggingInvalidWire(from_id, wire_type);
                             }
                         }
@@ -247,7 +507,7 @@ impl App {
                 } else {
                     match device_under_mouse {
                         Some(to_id) => {
-                            if self.session.can_connect(from_id, to_id) {
+                            if self.session.can_connect(from_id, to_id, feedback) {
                                 self.cursor =
                                     CursorState::DraggingConnectedWire(from_id, to_id, wire_type);
                             }
@@ -261,14 +521,19 @@ impl App {
                 if is_mouse_button_released(MouseButton::Left) {
                     self.cursor = CursorState::Idle;
                 } else {
-                    let top = f32::min(starting_corner.y, m_pos.y);
-                    let left = f32::min(starting_corner.x, m_pos.x);
-                    let delta = (m_pos - starting_corner).abs();
-                    let rect = Rect::new(left, top, delta.x, delta.y);
+                    let from = self.draw_ctx.viewport_to_world(starting_corner);
+                    let to = self.draw_ctx.viewport_to_world(m_pos);
+                    let top_left = from.min(to);
+                    let size = (to - from).abs();
+                    let rect = Rect::new(top_left.x, top_left.y, size.x, size.y);
 
+                    let (devices, comments) = &self.box_select_base;
                     self.session.clear_selection();
-                    self.session
-                        .select_devices_in_rect(rect.offset(self.draw_ctx.viewport_offset * -1.0));
+                    self.session.selected = devices.clone();
+                    for i in comments {
+                        self.session.select_comment(*i);
+                    }
+                    self.session.select_devices_in_rect(rect);
                 }
             }
 
@@ -280,36 +545,412 @@ impl App {
                     self.cursor = CursorState::Idle;
                 }
             }
+
+            CursorState::NavigatingMinimap => {
+                if let Some(minimap) = self.minimap() {
+                    let target = minimap.minimap_to_world(m_pos);
+                    self.draw_ctx.center_on(target, self.canvas);
+                }
+
+                if is_mouse_button_released(MouseButton::Left) {
+                    self.cursor = CursorState::Idle;
+                }
+            }
+        }
+
+        // keys typed into a text field are meant for the field, not shortcuts
+        if self.egui_wants_keyboard {
+            return;
         }
 
         if is_key_pressed(KeyCode::Delete) {
             self.session.delete_selected_devices();
         }
 
+        if is_key_pressed(KeyCode::Escape) {
+            self.session.clear_selection();
+        }
+
+        if is_key_pressed(KeyCode::Home) {
+            self.frame_all();
+        }
+
+        if is_key_pressed(KeyCode::F) {
+            self.frame_selection();
+        }
+
         if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl) {
             if is_key_pressed(KeyCode::C) {
-                self.session.copy_selected_devices();
+                self.copy_selection();
+            }
+
+            if is_key_pressed(KeyCode::X) {
+                self.copy_selection();
+                self.session.delete_selected_devices();
+            }
+
+            if is_key_pressed(KeyCode::D) {
+                self.session.duplicate_selected_devices();
+            }
+
+            if is_key_pressed(KeyCode::A) {
+                self.session.select_all_devices();
             }
 
             if is_key_pressed(KeyCode::V) {
-                self.session
-                    .paste_clipboard(self.draw_ctx.viewport_to_world(m_pos));
+                self.paste(self.draw_ctx.viewport_to_world(m_pos));
+            }
+
+            if is_key_pressed(KeyCode::P) {
+                self.panic();
             }
         }
 
         if is_key_pressed(KeyCode::Space) {
-            self.session.toggle_pause();
+            self.toggle_pause();
         }
     }
 
     pub fn update(&mut self) {
+        let result = self.midi_input.maintain_connections();
+        self.report_midi_result(result);
+        self.handle_midi_input();
+
         self.session.update();
+        self.analyzer
+            .forget_missing(|id| self.session.has_root_device(id));
+        self.analyzer.record(
+            self.session.update_ctx.beat_clock,
+            self.session.root_outputs(),
+        );
+
+        let result = self.midi_config.process_events();
+        self.report_midi_result(result);
+        self.midi_monitor.record(
+            self.session.update_ctx.beat_clock,
+            self.midi_config.take_sent_events(),
+        );
+
+        let result = self.midi_config.maintain_connections();
+        self.report_midi_result(result);
+    }
+
+    /// Flushes any pending MIDI and releases every held note before the app
+    /// closes, so nothing is left ringing on the connected hardware.
+    pub fn shutdown(&mut self) {
+        // errors don't matter anymore at this point
+        let _ = self.midi_config.process_events();
+        let _ = self.midi_config.release_held_notes();
+
+        self.save_config();
+    }
+
+    fn handle_midi_input(&mut self) {
+        for event in self.midi_input.receive_events() {
+            let Some((source, value)) = ControlSource::from_event(event) else {
+                continue;
+            };
+
+            if let Some(target) = self.learning.take() {
+                self.session.learn_mapping(source, target);
+                continue;
+            }
+
+            for target in self.session.apply_midi_control(source, value) {
+                match target {
+                    LearnTarget::PlayPause => self.toggle_pause(),
+                    LearnTarget::Reset => self.session.reset(),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn learn_menu(&mut self, response: &egui::Response, target: LearnTarget) {
+        match learn_menu(response) {
+            Some(LearnAction::Learn) => self.learning = Some(target),
+            Some(LearnAction::Forget) => self.session.forget_mapping(target),
+            None => {}
+        }
+    }
+
+    fn frame_all(&mut self) {
+        let ids: Vec<DeviceId> = self.session.devices.keys().copied().collect();
+        if let Some(bounds) = self.session.device_bounds(ids) {
+            self.draw_ctx.frame(bounds, self.canvas);
+        }
+    }
+
+    fn frame_selection(&mut self) {
+        let ids = self.session.selected.clone();
+        if let Some(bounds) = self.session.device_bounds(ids) {
+            self.draw_ctx.frame(bounds, self.canvas);
+        }
+    }
+
+    fn analyzer_ui(&mut self, ui: &mut egui::Ui) {
+        // only top level devices can be probed, like with MIDI mappings
+        let can_probe = !self.session.is_editing_subpatch()
+            && self
+                .session
+                .selected
+                .iter()
+                .any(|id| !self.analyzer.is_probed(*id));
+
+        let probe = ui.add_enabled(can_probe, egui::Button::new("Probe Selected"));
+        if probe.clicked() {
+            for id in self.session.selected.iter() {
+                let Some(device) = self.session.devices.get(id) else {
+                    continue;
+                };
+                if !device.has_output() {
+                    continue;
+                }
+                let name = match self.session.labels.get(id).filter(|l| !l.is_empty()) {
+                    Some(label) => label.clone(),
+                    None => format!("{} {}", device.kind(), id.as_u32()),
+                };
+                self.analyzer.probe(*id, name, device.output_kind());
+            }
+        }
+
+        self.analyzer
+            .ui(ui, self.session.update_ctx.bpm, &self.draw_ctx.colors);
+    }
+
+    fn minimap(&self) -> Option<Minimap> {
+        if !self.show_minimap {
+            return None;
+        }
+        let ids = self.session.devices.keys().copied();
+        let bounds = self.session.device_bounds(ids)?;
+        Some(Minimap::new(self.canvas, bounds))
+    }
+
+    fn copy_selection(&mut self) {
+        self.session.copy_selected_devices();
+
+        // also as text, so it can be pasted into another instance
+        if self.session.has_selection() {
+            let patch = write_patch(&self.session.clipboard);
+            clipboard_set(&patch);
+            self.copied_patch = Some(patch);
+        }
+    }
+
+    // prefers a patch on the system clipboard, which may have come from
+    // another instance, over the devices copied in this one. One this
+    // instance put there itself may be older than what was copied since.
+    fn paste(&mut self, position: Vec2) {
+        let Some(text) = clipboard_get()
+            .filter(|text| is_patch(text))
+            .filter(|text| self.copied_patch.as_ref() != Some(text))
+        else {
+            self.session.paste_clipboard(position);
+            return;
+        };
+
+        match read_patch(&text, &self.midi_config.get_event_sender()) {
+            Ok(snippet) => self.session.paste_snippet(snippet, position),
+            Err(err) => self.patch_error = Some(format!("Couldn't paste: {}", err)),
+        }
+    }
+
+    fn insert_library_entry(&mut self, name: &str, position: Vec2) {
+        let snippet = library::load_entry(name)
+            .map_err(|err| err.to_string())
+            .and_then(|text| {
+                read_patch(&text, &self.midi_config.get_event_sender())
+                    .map_err(|err| err.to_string())
+            });
+
+        match snippet {
+            Ok(snippet) => {
+                self.session.paste_snippet(snippet, position);
+                self.session.snap_selected_to_grid();
+            }
+            Err(err) => self.patch_error = Some(format!("Couldn't insert {}: {}", name, err)),
+        }
+    }
+
+    fn library_save_ui(&mut self, ctx: &egui::Context) {
+        let Some(name) = &mut self.library_save_name else {
+            return;
+        };
+
+        let mut save = false;
+        let mut cancel = false;
+        egui::Window::new("Save to Library")
+            .resizable(false)
+            .collapsible(false)
+            .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Name");
+                    ui.text_edit_singleline(name).request_focus();
+                });
+
+                let valid = library::is_valid_name(name);
+                if valid && self.library_entries.iter().any(|e| e == name.trim()) {
+                    ui.label("Replaces the entry with that name");
+                }
+
+                ui.horizontal(|ui| {
+                    let enter = ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    save = ui.add_enabled(valid, egui::Button::new("Save")).clicked()
+                        || (valid && enter);
+                    cancel = ui.button("Cancel").clicked()
+                        || ui.input(|i| i.key_pressed(egui::Key::Escape));
+                });
+            });
+
+        if save {
+            let patch = write_patch(&self.session.selected_snippet());
+            if let Err(err) = library::save_entry(name, &patch) {
+                self.patch_error = Some(format!("Couldn't save {}: {}", name.trim(), err));
+            }
+        }
+        if save || cancel {
+            self.library_save_name = None;
+        }
+    }
+
+    fn save_config(&mut self) {
+        self.config.input_ports = self.midi_input.wanted_ports().to_vec();
+        self.config.output_ports = self.midi_config.wanted_ports().to_vec();
+        self.config.virtual_port = self.midi_config.has_virtual_port();
+
+        if let Err(err) = self.config.save() {
+            self.patch_error = Some(format!("Couldn't save config: {}", err));
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        self.session.toggle_pause();
+
+        if self.session.update_ctx.is_paused {
+            self.release_device_notes();
+            let result = self.midi_config.release_held_notes();
+            self.report_midi_result(result);
+        }
+    }
+
+    fn panic(&mut self) {
+        self.release_device_notes();
+        let result = self.midi_config.panic();
+        self.report_midi_result(result);
+    }
+
+    // has the devices send note-offs for their notes right away, so they know
+    // the notes aren't sounding anymore
+    fn release_device_notes(&mut self) {
+        self.session.release_notes();
+        let result = self.midi_config.process_events();
+        self.report_midi_result(result);
+    }
+
+    fn report_midi_result(&mut self, result: Result<(), MidiError>) {
+        if let Err(err) = result {
+            self.midi_error = Some(err);
+        }
+    }
+
+    fn status_ui(&mut self, ui: &mut egui::Ui) {
+        // laid out right to left, so everything is added in reverse
+        if let Some(err) = &self.patch_error {
+            if ui.small_button("✖").clicked() {
+                self.patch_error = None;
+            } else {
+                ui.colored_label(color_to_color32(self.draw_ctx.colors.error), err);
+                ui.separator();
+            }
+        }
+
+        if let Some(err) = &self.midi_error {
+            if ui.small_button("✖").clicked() {
+                self.midi_error = None;
+            } else {
+                ui.colored_label(
+                    color_to_color32(self.draw_ctx.colors.error),
+                    format!("MIDI error: {}", err),
+                );
+                ui.separator();
+            }
+        }
+
+        if let Some(target) = self.learning {
+            if ui.small_button("✖").clicked() {
+                self.learning = None;
+            } else {
+                ui.label(format!(
+                    "Learning {}, move a MIDI control...",
+                    target.describe()
+                ));
+                ui.separator();
+            }
+        }
+
+        let missing: Vec<&String> = self
+            .midi_config
+            .missing_ports()
+            .chain(self.midi_input.missing_ports())
+            .collect();
+        if !missing.is_empty() {
+            ui.colored_label(
+                color_to_color32(self.draw_ctx.colors.fg_3),
+                format!("Waiting for {}", join_names(&missing)),
+            );
+            ui.separator();
+        }
+
+        let connected: Vec<&String> = self.midi_config.connected_ports().collect();
+        if self.midi_config.midi_out.is_none() {
+            ui.label("MIDI unavailable");
+        } else if connected.is_empty() {
+            ui.label("No MIDI output");
+        } else {
+            ui.label(format!("MIDI out: {}", join_names(&connected)));
+        }
+    }
 
-        self.midi_config.process_events();
+    fn port_choice_ui(&mut self, ctx: &egui::Context) {
+        let Some(choice) = &self.port_choice else {
+            return;
+        };
+
+        let mut chosen = None;
+        egui::Window::new("port choice")
+            .resizable(false)
+            .title_bar(false)
+            .fixed_pos(choice.position.to_array())
+            .show(ctx, |ui| {
+                ui.label("Plug into");
+                for port in choice.ports.iter() {
+                    let name = self.session.input_port_name(choice.to, *port);
+                    if ui.button(name).clicked() {
+                        chosen = Some(*port);
+                    }
+                }
+            });
+
+        if let Some(port) = chosen {
+            self.session.connect_devices(
+                choice.from,
+                choice.to,
+                choice.wire_type,
+                port,
+                choice.feedback,
+            );
+            self.port_choice = None;
+        } else if is_key_pressed(KeyCode::Escape) {
+            self.port_choice = None;
+        }
     }
 
     pub fn ui(&mut self, ctx: &egui::Context) {
         ctx.set_visuals(self.draw_ctx.egui_visuals.clone());
+        self.egui_wants_keyboard = ctx.wants_keyboard_input();
+        self.library_save_ui(ctx);
         if let Some(pos) = self.context_menu {
             egui::Window::new("context menu")
                 .resizable(false)
@@ -326,6 +967,26 @@ impl App {
                         self.session.add_device(Box::new(trigger));
                         self.context_menu = None;
                     }
+                    if ui.button("Edge").clicked() {
+                        let edge = Edge::new(self.draw_ctx.viewport_to_world(pos));
+                        self.session.add_device(Box::new(edge));
+                        self.context_menu = None;
+                    }
+                    if ui.button("Debounce").clicked() {
+                        let debounce = Debounce::new(self.draw_ctx.viewport_to_world(pos));
+                        self.session.add_device(Box::new(debounce));
+                        self.context_menu = None;
+                    }
+                    if ui.button("Ratchet").clicked() {
+                        let ratchet = Ratchet::new(self.draw_ctx.viewport_to_world(pos));
+                        self.session.add_device(Box::new(ratchet));
+                        self.context_menu = None;
+                    }
+                    if ui.button("Delay").clicked() {
+                        let delay = Delay::new(self.draw_ctx.viewport_to_world(pos));
+                        self.session.add_device(Box::new(delay));
+                        self.context_menu = None;
+                    }
                     if ui.button("Latch").clicked() {
                         let latch = Latch::new(self.draw_ctx.viewport_to_world(pos));
                         self.session.add_device(Box::new(latch));
@@ -336,6 +997,21 @@ impl App {
                         self.session.add_device(Box::new(gate));
                         self.context_menu = None;
                     }
+                    if ui.button("Counter").clicked() {
+                        let counter = Counter::new(self.draw_ctx.viewport_to_world(pos));
+                        self.session.add_device(Box::new(counter));
+                        self.context_menu = None;
+                    }
+                    if ui.button("Sample & Hold").clicked() {
+                        let sample_hold = SampleHold::new(self.draw_ctx.viewport_to_world(pos));
+                        self.session.add_device(Box::new(sample_hold));
+                        self.context_menu = None;
+                    }
+                    if ui.button("Quantizer").clicked() {
+                        let quantizer = Quantizer::new(self.draw_ctx.viewport_to_world(pos));
+                        self.session.add_device(Box::new(quantizer));
+                        self.context_menu = None;
+                    }
                     if ui.button("Note").clicked() {
                         let note = Note::new(
                             self.draw_ctx.viewport_to_world(pos),
@@ -344,6 +1020,78 @@ impl App {
                         self.session.add_device(Box::new(note));
                         self.context_menu = None;
                     }
+
+                    if ui.button("Comment").clicked() {
+                        let comment = Comment::new(self.draw_ctx.viewport_to_world(pos), "Comment");
+                        self.session.add_comment(comment);
+                        self.context_menu = None;
+                    }
+
+                    if !self.library_entries.is_empty() {
+                        ui.menu_button("Library", |ui| {
+                            for name in self.library_entries.clone() {
+                                if ui.button(&name).clicked() {
+                                    let position = self.draw_ctx.viewport_to_world(pos);
+                                    self.insert_library_entry(&name, position);
+                                    self.context_menu = None;
+                                }
+                            }
+                        });
+                    }
+
+                    ui.separator();
+
+                    let save = ui.add_enabled(
+                        self.session.has_selection(),
+                        egui::Button::new("Save Selection to Library..."),
+                    );
+                    if save.clicked() {
+                        self.library_save_name = Some(String::new());
+                        self.context_menu = None;
+                    }
+
+                    let mut group = ui.add_enabled(
+                        self.session.can_group_selected(),
+                        egui::Button::new("Group into Subpatch"),
+                    );
+                    if self.session.selection_is_mapped() {
+                        group = group.on_disabled_hover_text(
+                            "MIDI mappings can't reach into subpatches, forget the mappings of the selected devices first",
+                        );
+                    }
+                    if group.clicked() {
+                        self.session.group_selected_into_subpatch();
+                        self.context_menu = None;
+                    }
+
+                    if self.session.is_editing_subpatch() {
+                        let position = self.draw_ctx.viewport_to_world(pos);
+                        ui.menu_button("Subpatch Input", |ui| {
+                            for index in self.session.missing_inlets() {
+                                if ui.button(format!("Restore In {}", index + 1)).clicked() {
+                                    self.session.restore_inlet(index, position);
+                                    self.context_menu = None;
+                                }
+                            }
+                            if ui.button("New Bool Input").clicked() {
+                                self.session.add_subpatch_input(SignalKind::Bool, position);
+                                self.context_menu = None;
+                            }
+                            if ui.button("New Number Input").clicked() {
+                                self.session.add_subpatch_input(SignalKind::Number, position);
+                                self.context_menu = None;
+                            }
+                        });
+
+                        let outlet = ui.add_enabled(
+                            self.session.can_set_outlet(),
+                            egui::Button::new("Use as Subpatch Output"),
+                        );
+                        if outlet.clicked() {
+                            self.session.set_selected_as_outlet();
+                            self.context_menu = None;
+                        }
+                    }
                 });
 
             if is_key_pressed(KeyCode::Escape) {
@@ -351,24 +1099,192 @@ impl App {
             }
         }
 
+        self.port_choice_ui(ctx);
+
         egui::TopBottomPanel::top("top bar").show(ctx, |ui| {
             menu::bar(ui, |ui| {
                 ui.menu_button("MIDI Setup", |ui| {
                     ui.horizontal(|ui| {
-                        ui.label("Ports: ");
+                        ui.label("Outputs: ");
                         if ui.button("🔃").clicked() {
-                            self.midi_config.refresh_ports();
+                            let result = self.midi_config.refresh_ports();
+                            self.report_midi_result(result);
+                            let result = self.midi_input.refresh_ports();
+                            self.report_midi_result(result);
                         }
                     });
 
                     for (name, port, connected) in self.midi_config.ports.clone() {
+                        if ui.selectable_label(connected, &name).clicked() {
+                            let result = if connected {
+                                self.midi_config.disconnect_port(&name)
+                            } else {
+                                self.midi_config.connect_to_port(&port)
+                            };
+                            self.report_midi_result(result);
+                            self.save_config();
+                        }
+                    }
+
+                    #[cfg(unix)]
+                    {
+                        ui.separator();
+
+                        let mut virtual_port = self.midi_config.has_virtual_port();
                         if ui
-                            .add_enabled(!connected, egui::Button::new(name))
-                            .clicked()
+                            .checkbox(&mut virtual_port, "Virtual port \"graf\"")
+                            .changed()
                         {
-                            self.midi_config.connect_to_port(&port);
+                            let result = if virtual_port {
+                                self.midi_config.open_virtual_port()
+                            } else {
+                                self.midi_config.close_virtual_port()
+                            };
+                            self.report_midi_result(result);
+                            self.save_config();
+                        }
+                    }
+
+                    ui.separator();
+                    ui.label("Inputs: ");
+
+                    for (name, port, connected) in self.midi_input.ports.clone() {
+                        if ui.selectable_label(connected, &name).clicked() {
+                            if connected {
+                                self.midi_input.disconnect_port(&name);
+                            } else {
+                                let result = self.midi_input.connect_to_port(&port);
+                                self.report_midi_result(result);
+                            }
+                            self.save_config();
+                        }
+                    }
+                });
+
+                ui.menu_button("MIDI Mappings", |ui| {
+                    if self.session.midi_mappings.is_empty() {
+                        ui.label("Right-click a parameter and choose \"MIDI Learn\" to map it");
+                    }
+
+                    let mut forget = None;
+                    let mut inspect = None;
+                    egui::Grid::new("midi_mappings")
+                        .num_columns(5)
+                        .show(ui, |ui| {
+                            for mapping in self.session.midi_mappings.iter_mut() {
+                                let target = mapping.target;
+                                if let LearnTarget::Parameter(id, name) = target {
+                                    if ui.button(name).on_hover_text("Select device").clicked() {
+                                        inspect = Some(id);
+                                    }
+                                } else {
+                                    ui.label(target.describe());
+                                }
+
+                                ui.label(mapping.source.describe());
+
+                                if target.is_button() {
+                                    ui.label("");
+                                    ui.label("");
+                                } else {
+                                    ui.add(DragValue::new(&mut mapping.min).prefix("min "));
+                                    ui.add(DragValue::new(&mut mapping.max).prefix("max "));
+                                }
+
+                                if ui.small_button("✖").clicked() {
+                                    forget = Some(target);
+                                }
+                                ui.end_row();
+                            }
+                        });
+
+                    if let Some(target) = forget {
+                        self.session.forget_mapping(target);
+                    }
+                    if let Some(id) = inspect {
+                        self.session.clear_selection();
+                        self.session.select_device(id);
+                    }
+                });
+
+                ui.menu_button("Settings", |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Theme");
+                        for (theme, name) in [(Theme::Dark, "Dark"), (Theme::Light, "Light")] {
+                            if ui
+                                .selectable_label(self.config.theme == theme, name)
+                                .clicked()
+                            {
+                                self.config.theme = theme;
+                                self.draw_ctx.set_palette(theme.palette());
+                                self.save_config();
+                            }
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Default BPM");
+                        let response = ui.add(
+                            DragValue::new(&mut self.config.default_bpm).range(MIN_BPM..=MAX_BPM),
+                        );
+                        if response.drag_stopped() || (response.changed() && !response.dragged()) {
+                            self.save_config();
                         }
+                    });
+                });
+
+                ui.menu_button("View", |ui| {
+                    if ui.button("Frame All (Home)").clicked() {
+                        self.frame_all();
+                        ui.close_menu();
+                    }
+                    let frame_selection = ui.add_enabled(
+                        !self.session.selected.is_empty(),
+                        egui::Button::new("Frame Selection (F)"),
+                    );
+                    if frame_selection.clicked() {
+                        self.frame_selection();
+                        ui.close_menu();
                     }
+
+                    ui.separator();
+
+                    ui.checkbox(&mut self.show_minimap, "Minimap");
+                    ui.checkbox(&mut self.show_analyzer, "Logic Analyzer");
+                    ui.checkbox(&mut self.show_midi_monitor, "MIDI Monitor");
+                });
+
+                ui.menu_button("Transpose", |ui| {
+                    let transpose = &mut self.session.update_ctx.transpose;
+                    egui::Grid::new("channel_transpose")
+                        .num_columns(2)
+                        .show(ui, |ui| {
+                            for (channel, semitones) in transpose.channels.iter_mut().enumerate() {
+                                ui.label(format!("Channel {}", channel));
+                                ui.add(DragValue::new(semitones).range(-48..=48));
+                                ui.end_row();
+                            }
+                        });
+
+                    if ui.button("Reset All").clicked() {
+                        *transpose = Transpose::new();
+                    }
+                });
+
+                if ui
+                    .button("Panic")
+                    .on_hover_text("Send All Notes Off on every channel (Ctrl+P)")
+                    .clicked()
+                {
+                    self.panic();
+                }
+
+                if self.session.is_editing_subpatch() && ui.button("⬆ Parent Patch").clicked() {
+                    self.session.close_subpatch();
+                }
+
+                ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                    self.status_ui(ui);
                 });
             });
         });
@@ -376,7 +1292,16 @@ impl App {
         egui::TopBottomPanel::bottom("bottom bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.label("BPM");
-                ui.add(egui::DragValue::new(&mut self.session.update_ctx.bpm).range(20..=777));
+                let bpm = ui.add(DragValue::new(&mut self.session.update_ctx.bpm).range(20..=777));
+                self.learn_menu(&bpm, LearnTarget::Bpm);
+
+                ui.separator();
+
+                let transpose = &mut self.session.update_ctx.transpose;
+                ui.label("Transpose");
+                ui.add(DragValue::new(&mut transpose.semitones).range(-48..=48));
+                ui.label("Octave");
+                ui.add(DragValue::new(&mut transpose.octaves).range(-4..=4));
 
                 ui.separator();
 
@@ -387,31 +1312,91 @@ impl App {
                 } else {
                     "Pause"
                 };
-                if ui.button(pause_play_text).clicked() {
-                    self.session.toggle_pause();
+                let pause_play = ui.button(pause_play_text);
+                if pause_play.clicked() {
+                    self.toggle_pause();
                 }
+                self.learn_menu(&pause_play, LearnTarget::PlayPause);
 
-                if ui.button("Reset").clicked() {
+                let reset = ui.button("Reset");
+                if reset.clicked() {
                     self.session.reset();
                 }
+                self.learn_menu(&reset, LearnTarget::Reset);
             });
         });
 
-        if let [selected_id] = self.session.selected.as_slice() {
+        if self.show_analyzer {
+            egui::TopBottomPanel::bottom("logic analyzer")
+                .resizable(true)
+                .show(ctx, |ui| {
+                    egui::ScrollArea::vertical().show(ui, |ui| self.analyzer_ui(ui));
+                });
+        }
+
+        if self.show_midi_monitor {
+            egui::TopBottomPanel::bottom("midi monitor")
+                .resizable(true)
+                .show(ctx, |ui| self.midi_monitor.ui(ui, &self.draw_ctx.colors));
+        }
+
+        let canvas = ctx.available_rect();
+        self.canvas = Rect::new(canvas.min.x, canvas.min.y, canvas.width(), canvas.height());
+
+        if let [selected_id] = *self.session.selected.as_slice() {
             match self.session.devices.get_mut(&selected_id) {
                 Some(dev) => {
+                    let label = self.session.labels.entry(selected_id).or_default();
                     egui::Window::new("Edit Device")
                         .anchor(Align2::RIGHT_TOP, [-10.0, 30.0])
                         .movable(false)
                         .title_bar(false)
                         .default_width(INSPECTOR_WIDTH)
                         .resizable(false)
-                        .show(ctx, |ui| dev.inspector(ui));
+                        .show(ctx, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Label");
+                                ui.text_edit_singleline(label);
+                            });
+                            ui.separator();
+                            dev.inspector(ui);
+                        });
                 }
                 None => {
                     panic!("Tried to inspect device that doesn't exist???")
                 }
             }
+
+            // mappings can only target devices in the top level patch
+            match take_parameter_request(ctx) {
+                _ if self.session.is_editing_subpatch() => {}
+                Some((LearnAction::Learn, name)) => {
+                    self.learning = Some(LearnTarget::Parameter(selected_id, name));
+                }
+                Some((LearnAction::Forget, name)) => {
+                    self.session
+                        .forget_mapping(LearnTarget::Parameter(selected_id, name));
+                }
+                None => {}
+            }
+        }
+
+        if let Some(comment) = self.session.selected_comment_mut() {
+            egui::Window::new("Edit Comment")
+                .anchor(Align2::RIGHT_TOP, [-10.0, 30.0])
+                .movable(false)
+                .title_bar(false)
+                .default_width(INSPECTOR_WIDTH)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(
+                        RichText::new("Comment")
+                            .font(FontId::proportional(16.0))
+                            .strong(),
+                    );
+                    ui.separator();
+                    ui.text_edit_multiline(&mut comment.text);
+                });
         }
     }
 
@@ -424,7 +1409,8 @@ impl App {
         match self.cursor {
             CursorState::Idle
             | CursorState::DraggingSelectedDevices(_)
-            | CursorState::PanningViewport(_) => {}
+            | CursorState::PanningViewport(_)
+            | CursorState::NavigatingMinimap => {}
 
             CursorState::DraggingLooseWire(from_id, wire_type) => {
                 let from_dev = self.session.devices.get(&from_id).unwrap();
@@ -433,6 +1419,7 @@ impl App {
                     from_dev.as_ref(),
                     m_pos,
                     wire_type,
+                    feedback_key_down(),
                     self.draw_ctx.colors.fg_2,
                 );
             }
@@ -444,6 +1431,7 @@ impl App {
                     from_dev.as_ref(),
                     to_dev.as_ref(),
                     wire_type,
+                    feedback_key_down() || self.session.closes_loop(from_id, to_id),
                     self.draw_ctx.colors.fg_0,
                 );
             }
@@ -454,6 +1442,7 @@ impl App {
                     from_dev.as_ref(),
                     m_pos,
                     wire_type,
+                    feedback_key_down(),
                     self.draw_ctx.colors.error,
                 );
             }
@@ -466,5 +1455,34 @@ impl App {
         }
 
         self.session.draw(&self.draw_ctx);
+
+        if let Some(minimap) = self.minimap() {
+            let devices: Vec<(Vec2, bool)> = self
+                .session
+                .devices
+                .iter()
+                .map(|(id, dev)| (dev.get_position(), self.session.selected.contains(id)))
+                .collect();
+            let view = self.draw_ctx.view_rect(self.canvas);
+            minimap.draw(&self.draw_ctx, &devices, view);
+        }
     }
 }
+
+fn shift_key_down() -> bool {
+    is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift)
+}
+
+// holding alt while dragging a wire makes it a feedback wire, which reads the
+// previous update's output of its source and so is allowed to close a loop
+fn feedback_key_down() -> bool {
+    is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt)
+}
+
+fn join_names(names: &[&String]) -> String {
+    names
+        .iter()
+        .map(|name| name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
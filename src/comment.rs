@@ -0,0 +1,80 @@
+use macroquad::{
+    math::{vec2, Rect, Vec2},
+    shapes::{draw_rectangle, draw_rectangle_lines},
+    text::{draw_text, measure_text},
+};
+
+use crate::app::DrawContext;
+
+const COMMENT_FONT_SIZE: f32 = 14.0;
+const COMMENT_LINE_HEIGHT: f32 = 16.0;
+const COMMENT_PADDING: f32 = 6.0;
+
+/// A box of text that can be put anywhere on the canvas. It isn't part of the
+/// circuit, it's only there to explain the patch.
+#[derive(Clone)]
+pub struct Comment {
+    // top-left corner
+    pub position: Vec2,
+    pub text: String,
+    pub selected: bool,
+}
+
+impl Comment {
+    pub fn new(position: Vec2, text: &str) -> Self {
+        Comment {
+            position,
+            text: text.to_string(),
+            selected: false,
+        }
+    }
+
+    /// Area the comment covers, in world coordinates
+    pub fn rect(&self) -> Rect {
+        let width = self
+            .text
+            .lines()
+            .map(|line| measure_text(line, None, COMMENT_FONT_SIZE as u16, 1.0).width)
+            .fold(0.0, f32::max);
+        let lines = self.text.lines().count().max(1);
+
+        Rect::new(
+            self.position.x,
+            self.position.y,
+            width + 2.0 * COMMENT_PADDING,
+            lines as f32 * COMMENT_LINE_HEIGHT + 2.0 * COMMENT_PADDING,
+        )
+    }
+
+    pub fn draw(&self, ctx: &DrawContext) {
+        let scale = ctx.scale;
+        let rect = self.rect();
+        let top_left = ctx.world_to_viewport(rect.point());
+        let size = rect.size() * scale;
+
+        if self.selected {
+            draw_rectangle_lines(
+                top_left.x - 4.0,
+                top_left.y - 4.0,
+                size.x + 8.0,
+                size.y + 8.0,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
+        }
+
+        draw_rectangle(top_left.x, top_left.y, size.x, size.y, ctx.colors.bg_1);
+        draw_rectangle_lines(top_left.x, top_left.y, size.x, size.y, 1.0, ctx.colors.bg_3);
+
+        let font_size = COMMENT_FONT_SIZE * scale;
+        let line_offset = measure_text("A", None, font_size as u16, 1.0).offset_y;
+        for (i, line) in self.text.lines().enumerate() {
+            let pos = top_left
+                + vec2(
+                    COMMENT_PADDING,
+                    COMMENT_PADDING + i as f32 * COMMENT_LINE_HEIGHT,
+                ) * scale;
+            draw_text(line, pos.x, pos.y + line_offset, font_size, ctx.colors.fg_2);
+        }
+    }
+}
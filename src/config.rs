@@ -0,0 +1,110 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{drawing_utils::Theme, midi::stable_port_name};
+
+// range of tempos the settings allow for the default BPM
+pub const MIN_BPM: u32 = 20;
+pub const MAX_BPM: u32 = 777;
+
+/// Per-machine settings, kept separate from patches so that patches stay
+/// portable between machines.
+///
+/// Stored as simple `key = value` lines in the XDG config directory. Keys that
+/// hold lists (like ports) are just repeated once per item.
+pub struct AppConfig {
+    pub input_ports: Vec<String>,
+    pub output_ports: Vec<String>,
+    pub virtual_port: bool,
+    pub theme: Theme,
+    pub default_bpm: u32,
+}
+
+impl AppConfig {
+    pub fn new() -> Self {
+        AppConfig {
+            input_ports: Vec::new(),
+            output_ports: Vec::new(),
+            virtual_port: false,
+            theme: Theme::Dark,
+            default_bpm: 120,
+        }
+    }
+
+    /// Loads the config file, falling back to defaults for anything missing
+    /// or unreadable
+    pub fn load() -> Self {
+        let mut config = AppConfig::new();
+
+        let Some(contents) = config_path().and_then(|path| fs::read_to_string(path).ok()) else {
+            return config;
+        };
+
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            match (key.trim(), value.trim()) {
+                // older configs saved ports along with their ALSA numbers
+                ("input_port", name) => config.input_ports.push(stable_port_name(name)),
+                ("output_port", name) => config.output_ports.push(stable_port_name(name)),
+                ("virtual_port", on) => config.virtual_port = on == "true",
+                ("theme", "dark") => config.theme = Theme::Dark,
+                ("theme", "light") => config.theme = Theme::Light,
+                ("default_bpm", bpm) => {
+                    if let Ok(bpm) = bpm.parse::<u32>() {
+                        config.default_bpm = bpm.clamp(MIN_BPM, MAX_BPM);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        config
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = config_path() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "could not find a config directory",
+            ));
+        };
+
+        let mut contents = String::new();
+        let theme = match self.theme {
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+        };
+        contents.push_str(&format!("theme = {}\n", theme));
+        contents.push_str(&format!("default_bpm = {}\n", self.default_bpm));
+        contents.push_str(&format!("virtual_port = {}\n", self.virtual_port));
+        for name in self.input_ports.iter() {
+            contents.push_str(&format!("input_port = {}\n", name));
+        }
+        for name in self.output_ports.iter() {
+            contents.push_str(&format!("output_port = {}\n", name));
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, contents)
+    }
+}
+
+/// Directory for graf's per-user files, following the XDG base directory spec
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("graf"))
+}
+
+fn config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config"))
+}
@@ -6,6 +6,13 @@ use std::{
 #[derive(PartialEq, Eq, Clone, Copy, Hash)]
 pub struct DeviceId(u32);
 
+impl DeviceId {
+    // ids are written out as plain numbers in saved patches
+    pub fn as_u32(&self) -> u32 {
+        self.0
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Copy, Hash)]
 pub enum WireType {
     Normal,
@@ -17,10 +24,20 @@ pub struct Wire {
     pub from: DeviceId,
     pub to: DeviceId,
     pub wire_type: WireType,
+
+    // which input of the receiving device the wire is plugged into, 0 being
+    // the main input and anything above that one of its aux inputs
+    pub port: usize,
+
+    // feedback wires carry the value their source had on the previous update,
+    // which is what lets them close loops. they are left out of the
+    // topological order and transitive closure
+    pub feedback: bool,
 }
 
 pub struct IllegalWireError;
 
+#[derive(Clone)]
 pub struct Dag {
     id_counter: u32,
     wires: Vec<Wire>,
@@ -56,9 +73,11 @@ impl Dag {
         self.topological_order.contains(&d)
     }
 
-    pub fn contains_wire(&self, from: DeviceId, to: DeviceId) -> bool {
+    // a device can be wired into several inputs of another, so wires are told
+    // apart by their port too
+    pub fn contains_wire(&self, from: DeviceId, to: DeviceId, port: usize) -> bool {
         for wire in self.wires.iter() {
-            if wire.from == from && wire.to == to {
+            if wire.from == from && wire.to == to && wire.port == port {
                 return true;
             }
         }
@@ -89,10 +108,16 @@ impl Dag {
         from: DeviceId,
         to: DeviceId,
         edge_type: WireType,
+        port: usize,
+        feedback: bool,
     ) -> Result<(), IllegalWireError> {
-        if self.contains_wire(from, to) {
+        if self.contains_wire(from, to, port) {
             Ok(())
-        } else if self.is_reachable(to, from) {
+        } else if from == to {
+            // nothing can be drawn or clicked for a wire into its own source,
+            // even a feedback one
+            Err(IllegalWireError)
+        } else if !feedback && self.is_reachable(to, from) {
             // edge would create cycle
             Err(IllegalWireError)
         } else if self.contains_device(from) && self.contains_device(to) {
@@ -100,6 +125,8 @@ impl Dag {
                 from,
                 to,
                 wire_type: edge_type,
+                port,
+                feedback,
             };
             self.wires.push(e);
             self.recompute_caches();
@@ -117,8 +144,9 @@ impl Dag {
         self.recompute_caches();
     }
 
-    pub fn remove_wire(&mut self, from: DeviceId, to: DeviceId) {
-        self.wires.retain(|x| x.from != from || x.to != to);
+    pub fn remove_wire(&mut self, from: DeviceId, to: DeviceId, port: usize) {
+        self.wires
+            .retain(|x| x.from != from || x.to != to || x.port != port);
 
         self.recompute_caches();
     }
@@ -146,7 +174,7 @@ impl Dag {
             closure.get_mut(v).unwrap().insert(*v);
             let v_reachable = closure[v].clone();
 
-            for edge in self.wires.iter() {
+            for edge in self.wires.iter().filter(|e| !e.feedback) {
                 if *v == edge.to {
                     closure
                         .get_mut(&edge.from)
@@ -169,7 +197,7 @@ impl Dag {
         for vid in self.topological_order.iter() {
             incoming.insert(vid.to_owned(), 0);
         }
-        for edge in self.wires.iter() {
+        for edge in self.wires.iter().filter(|e| !e.feedback) {
             *incoming.get_mut(&edge.to).unwrap() += 1;
         }
 
@@ -191,7 +219,7 @@ impl Dag {
             topo.push(v);
 
             // remove outgoing edges from that vertex, and add any new sources
-            for edge in self.wires.iter() {
+            for edge in self.wires.iter().filter(|e| !e.feedback) {
                 if edge.from == v {
                     *incoming.get_mut(&edge.to).unwrap() -= 1;
 
@@ -4,9 +4,14 @@ use macroquad::{
     shapes::{draw_arc, draw_circle, draw_circle_lines},
 };
 
-use crate::{app::DrawContext, session::UpdateContext};
+use crate::{
+    app::DrawContext,
+    midi_learn::parameter_learn_menu,
+    patch::{DeviceEntry, PatchWriter},
+    session::UpdateContext,
+};
 
-use super::{Arity, Device, CLOCK_RADIUS};
+use super::{Arity, Device, Parameter, Signal, CLOCK_RADIUS};
 
 #[derive(Clone)]
 pub struct Clock {
@@ -42,6 +47,10 @@ impl Clock {
             cycle_position: 0.0,
         }
     }
+
+    fn is_on(&self) -> bool {
+        self.cycle_position <= self.gate
+    }
 }
 
 impl Device for Clock {
@@ -62,7 +71,7 @@ impl Device for Clock {
         self.position.distance(pt) <= CLOCK_RADIUS
     }
 
-    fn update(&mut self, ctx: &mut UpdateContext, _inputs: Vec<bool>) -> Option<bool> {
+    fn update(&mut self, ctx: &mut UpdateContext, _inputs: Vec<Signal>) -> Option<Signal> {
         if self.bpm_sync {
             let (numerator, denominator) = self.bpm_duration;
             let beat_period = (numerator as f32 / denominator as f32) * 4.0;
@@ -73,11 +82,7 @@ impl Device for Clock {
                 ((ctx.free_clock.as_secs_f32() * 1000.0 / self.free_duration) + self.offset) % 1.0;
         }
 
-        if self.cycle_position <= self.gate {
-            Some(true)
-        } else {
-            Some(false)
-        }
+        Some(Signal::Bool(self.is_on()))
     }
 
     fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
@@ -85,7 +90,13 @@ impl Device for Clock {
         let Vec2 { x, y } = position;
 
         if is_selected {
-            draw_circle_lines(x, y, radius + 4.0, 1.0, ctx.colors.fg_0.with_alpha(0.5));
+            draw_circle_lines(
+                x,
+                y,
+                radius + 4.0 * ctx.scale,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
         }
 
         draw_circle_lines(x, y, radius, 1.0, ctx.colors.fg_0);
@@ -101,6 +112,10 @@ impl Device for Clock {
             360.0 * self.gate,
             ctx.colors.fg_0,
         );
+
+        if self.is_on() {
+            draw_circle_lines(x, y, radius, 2.0, ctx.colors.active);
+        }
     }
 
     fn inspector(&mut self, ui: &mut egui::Ui) {
@@ -125,14 +140,59 @@ impl Device for Clock {
             ui.add(Slider::new(&mut self.free_duration, 1f32..=10000f32).text("Period"));
         }
 
-        ui.add(Slider::new(&mut self.gate, 0f32..=1.0f32).text("Gate"));
-        ui.add(Slider::new(&mut self.offset, 0f32..=1.0f32).text("Offset"));
+        let gate = ui.add(Slider::new(&mut self.gate, 0f32..=1.0f32).text("Gate"));
+        parameter_learn_menu(&gate, "Gate");
+        let offset = ui.add(Slider::new(&mut self.offset, 0f32..=1.0f32).text("Offset"));
+        parameter_learn_menu(&offset, "Offset");
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter {
+                name: "Gate",
+                min: 0.0,
+                max: 1.0,
+            },
+            Parameter {
+                name: "Offset",
+                min: 0.0,
+                max: 1.0,
+            },
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "Gate" => self.gate = value.clamp(0.0, 1.0),
+            "Offset" => self.offset = value.clamp(0.0, 1.0),
+            _ => {}
+        }
     }
 
     fn reset(&mut self) {
         self.cycle_position = 0.0;
     }
 
+    fn kind(&self) -> &'static str {
+        "clock"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("bpm_sync", self.bpm_sync);
+        patch.setting("free_duration", self.free_duration);
+        patch.fraction("bpm_duration", self.bpm_duration);
+        patch.setting("gate", self.gate);
+        patch.setting("offset", self.offset);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        entry.read("bpm_sync", &mut self.bpm_sync);
+        entry.read_clamped("free_duration", &mut self.free_duration, 1.0..=10000.0);
+        entry.read_fraction("bpm_duration", &mut self.bpm_duration, 1..=256);
+        entry.read_clamped("gate", &mut self.gate, 0.0..=1.0);
+        entry.read_clamped("offset", &mut self.offset, 0.0..=1.0);
+    }
+
     fn input_arity(&self) -> Arity {
         Arity::Nullary
     }
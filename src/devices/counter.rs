@@ -0,0 +1,170 @@
+use egui::{DragValue, FontId, RichText};
+use macroquad::{
+    math::Vec2,
+    shapes::{draw_poly, draw_poly_lines},
+    text::{draw_text, measure_text},
+};
+
+use crate::{
+    app::DrawContext,
+    patch::{DeviceEntry, PatchWriter},
+    session::UpdateContext,
+};
+
+use super::{Arity, AuxInput, Device, Signal, SignalKind, COUNTER_RADIUS};
+
+#[derive(Clone)]
+pub struct Counter {
+    position: Vec2,
+
+    // count wraps back to zero when it reaches this
+    modulo: u32,
+
+    count: u32,
+    prev_input: bool,
+
+    // the reset input holds the count at zero while it's on
+    reset_on: bool,
+}
+
+impl Counter {
+    pub fn new(position: Vec2) -> Self {
+        Counter {
+            position,
+
+            modulo: 8,
+
+            count: 0,
+            prev_input: false,
+
+            reset_on: false,
+        }
+    }
+}
+
+impl Device for Counter {
+    fn get_position(&self) -> Vec2 {
+        self.position
+    }
+
+    fn set_position(&mut self, pos: Vec2) {
+        self.position = pos;
+    }
+
+    fn closest_border_point(&self, point: Vec2, padding: f32) -> Vec2 {
+        let delta = point - self.position;
+        let u = delta.x.abs() + delta.y.abs();
+        if u == 0.0 {
+            return self.position;
+        }
+        self.position + delta * (COUNTER_RADIUS + padding) / u
+    }
+
+    fn is_point_inside(&self, pt: Vec2) -> bool {
+        let delta = pt - self.position;
+        delta.x.abs() + delta.y.abs() <= COUNTER_RADIUS
+    }
+
+    fn update(&mut self, _ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
+        let input_on = inputs.first().map(Signal::as_bool).unwrap_or(false);
+
+        if self.reset_on {
+            self.count = 0;
+        } else if input_on && !self.prev_input {
+            self.count = (self.count + 1) % self.modulo;
+        }
+        self.prev_input = input_on;
+
+        Some(Signal::Number(self.count as f32))
+    }
+
+    fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
+        let Vec2 { x, y } = position;
+        let radius = size * COUNTER_RADIUS / 24.0;
+
+        if is_selected {
+            draw_poly_lines(
+                x,
+                y,
+                4,
+                radius + 4.0 * ctx.scale,
+                0.0,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
+        }
+
+        draw_poly(x, y, 4, radius, 0.0, ctx.colors.bg_1);
+        draw_poly_lines(x, y, 4, radius, 0.0, 1.0, ctx.colors.fg_0);
+
+        let text = self.count.to_string();
+        let font_size = (14.0 * size / 24.0) as u16;
+        let dims = measure_text(&text, None, font_size, 1.0);
+        draw_text(
+            &text,
+            x - dims.width / 2.0,
+            y + dims.offset_y / 2.0,
+            font_size as f32,
+            ctx.colors.fg_0,
+        );
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    fn inspector(&mut self, ui: &mut egui::Ui) {
+        ui.label(
+            RichText::new("Counter")
+                .font(FontId::proportional(16.0))
+                .strong(),
+        );
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Modulo");
+            ui.add(DragValue::new(&mut self.modulo).range(1..=1024));
+        });
+        self.count %= self.modulo;
+
+        ui.label(format!("Count: {}", self.count));
+    }
+
+    fn kind(&self) -> &'static str {
+        "counter"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("modulo", self.modulo);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        entry.read_clamped("modulo", &mut self.modulo, 1..=1024);
+    }
+
+    fn input_arity(&self) -> Arity {
+        Arity::Unary
+    }
+
+    fn aux_inputs(&self) -> Vec<AuxInput> {
+        vec![AuxInput::new("Reset", SignalKind::Bool)]
+    }
+
+    fn set_aux_input(&mut self, port: usize, value: Option<Signal>) {
+        if port == 1 {
+            self.reset_on = value.is_some_and(|v| v.as_bool());
+        }
+    }
+
+    fn has_output(&self) -> bool {
+        true
+    }
+
+    fn output_kind(&self) -> SignalKind {
+        SignalKind::Number
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
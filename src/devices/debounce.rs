@@ -0,0 +1,233 @@
+use std::str::FromStr;
+
+use egui::{FontId, RichText};
+use macroquad::{
+    math::Vec2,
+    shapes::{draw_circle, draw_circle_lines, draw_line},
+};
+
+use crate::{
+    app::DrawContext,
+    midi_learn::parameter_learn_menu,
+    patch::{DeviceEntry, PatchWriter},
+    session::UpdateContext,
+};
+
+use super::{Arity, Device, Parameter, Signal, SyncedDuration, DEBOUNCE_RADIUS};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DebounceMode {
+    // input has to hold steady for the whole window before the output follows
+    Stable,
+
+    // output turns on right away, then stays on for at least the window
+    MinOn,
+
+    // output turns off right away, then stays off for at least the window
+    MinOff,
+}
+
+impl DebounceMode {
+    fn name(&self) -> &'static str {
+        match self {
+            DebounceMode::Stable => "stable",
+            DebounceMode::MinOn => "min_on",
+            DebounceMode::MinOff => "min_off",
+        }
+    }
+}
+
+impl FromStr for DebounceMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stable" => Ok(DebounceMode::Stable),
+            "min_on" => Ok(DebounceMode::MinOn),
+            "min_off" => Ok(DebounceMode::MinOff),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Debounce {
+    position: Vec2,
+
+    mode: DebounceMode,
+
+    window: SyncedDuration,
+
+    input_on: bool,
+    output_on: bool,
+
+    // times (in ms of the free clock) the input and output last changed
+    input_changed_at: f32,
+    output_changed_at: f32,
+}
+
+impl Debounce {
+    pub fn new(position: Vec2) -> Self {
+        Debounce {
+            position,
+
+            mode: DebounceMode::Stable,
+
+            window: SyncedDuration::new(false, 20.0, 1000.0, (1, 32)),
+
+            input_on: false,
+            output_on: false,
+
+            input_changed_at: f32::NEG_INFINITY,
+            output_changed_at: f32::NEG_INFINITY,
+        }
+    }
+}
+
+impl Device for Debounce {
+    fn get_position(&self) -> Vec2 {
+        self.position
+    }
+
+    fn set_position(&mut self, pos: Vec2) {
+        self.position = pos;
+    }
+
+    fn closest_border_point(&self, point: Vec2, padding: f32) -> Vec2 {
+        let delta = point - self.position;
+        self.position + delta.normalize() * (DEBOUNCE_RADIUS + padding)
+    }
+
+    fn is_point_inside(&self, pt: Vec2) -> bool {
+        self.position.distance(pt) <= DEBOUNCE_RADIUS
+    }
+
+    fn update(&mut self, ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
+        let input_on = inputs.first().map(Signal::as_bool).unwrap_or(false);
+        let now = ctx.free_clock.as_secs_f32() * 1000.0;
+        let window = self.window.as_ms(ctx);
+
+        if input_on != self.input_on {
+            self.input_on = input_on;
+            self.input_changed_at = now;
+        }
+
+        if input_on != self.output_on {
+            let held = match self.mode {
+                DebounceMode::Stable => now - self.input_changed_at,
+                DebounceMode::MinOn if input_on => f32::INFINITY,
+                DebounceMode::MinOff if !input_on => f32::INFINITY,
+                DebounceMode::MinOn | DebounceMode::MinOff => now - self.output_changed_at,
+            };
+
+            if held >= window {
+                self.output_on = input_on;
+                self.output_changed_at = now;
+            }
+        }
+
+        Some(Signal::Bool(self.output_on))
+    }
+
+    fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
+        let radius = size / 2.0;
+        let Vec2 { x, y } = position;
+
+        if is_selected {
+            draw_circle_lines(
+                x,
+                y,
+                radius + 4.0 * ctx.scale,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
+        }
+
+        draw_circle(x, y, radius, ctx.colors.bg_1);
+        draw_circle_lines(x, y, radius, 1.0, ctx.colors.fg_0);
+
+        if self.output_on {
+            draw_circle(x, y, radius / 2.0, ctx.colors.fg_0);
+        } else {
+            draw_line(
+                x - radius / 2.0,
+                y,
+                x + radius / 2.0,
+                y,
+                1.0,
+                ctx.colors.fg_0,
+            );
+        }
+    }
+
+    fn reset(&mut self) {
+        self.input_on = false;
+        self.output_on = false;
+
+        self.input_changed_at = f32::NEG_INFINITY;
+        self.output_changed_at = f32::NEG_INFINITY;
+    }
+
+    fn inspector(&mut self, ui: &mut egui::Ui) {
+        ui.label(
+            RichText::new("Debounce")
+                .font(FontId::proportional(16.0))
+                .strong(),
+        );
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.mode, DebounceMode::Stable, "Stable")
+                .on_hover_text("Ignore changes that don't last the whole window");
+            ui.selectable_value(&mut self.mode, DebounceMode::MinOn, "Min On")
+                .on_hover_text("Stay on for at least the window");
+            ui.selectable_value(&mut self.mode, DebounceMode::MinOff, "Min Off")
+                .on_hover_text("Stay off for at least the window");
+        });
+
+        ui.label("Window");
+        if let Some(window) = self.window.inspector(ui, "Length") {
+            parameter_learn_menu(&window, "Window");
+        }
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![Parameter {
+            name: "Window",
+            min: 1.0,
+            max: 1000.0,
+        }]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        if name == "Window" {
+            self.window.set_free_duration(value);
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "debounce"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("mode", self.mode.name());
+        self.window.save(patch);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        entry.read("mode", &mut self.mode);
+        self.window.load(entry);
+    }
+
+    fn input_arity(&self) -> Arity {
+        Arity::Unary
+    }
+
+    fn has_output(&self) -> bool {
+        true
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+use egui::{FontId, RichText};
+use macroquad::{
+    math::Vec2,
+    shapes::{draw_line, draw_rectangle, draw_rectangle_lines},
+};
+
+use crate::{
+    app::DrawContext,
+    midi_learn::parameter_learn_menu,
+    patch::{DeviceEntry, PatchWriter},
+    session::UpdateContext,
+};
+
+use super::{Arity, Device, Parameter, Signal, SyncedDuration, DELAY_HEIGHT, DELAY_WIDTH};
+
+#[derive(Clone)]
+pub struct Delay {
+    position: Vec2,
+
+    time: SyncedDuration,
+
+    // times (in ms of the free clock) and new values of every input change
+    // that hasn't come out the other end yet
+    history: VecDeque<(f32, bool)>,
+
+    input_on: bool,
+    output_on: bool,
+}
+
+impl Delay {
+    pub fn new(position: Vec2) -> Self {
+        Delay {
+            position,
+
+            time: SyncedDuration::new(true, 250.0, 10000.0, (1, 8)),
+
+            history: VecDeque::new(),
+
+            input_on: false,
+            output_on: false,
+        }
+    }
+}
+
+impl Device for Delay {
+    fn get_position(&self) -> Vec2 {
+        self.position
+    }
+
+    fn set_position(&mut self, pos: Vec2) {
+        self.position = pos;
+    }
+
+    fn closest_border_point(&self, point: Vec2, padding: f32) -> Vec2 {
+        let delta = point - self.position;
+        let u = f32::max(
+            delta.x.abs() / (DELAY_WIDTH / 2.0 + padding),
+            delta.y.abs() / (DELAY_HEIGHT / 2.0 + padding),
+        );
+        if u == 0.0 {
+            return self.position;
+        }
+        self.position + delta / u
+    }
+
+    fn is_point_inside(&self, pt: Vec2) -> bool {
+        let dx = (pt.x - self.position.x).abs();
+        let dy = (pt.y - self.position.y).abs();
+        dx <= DELAY_WIDTH / 2.0 && dy <= DELAY_HEIGHT / 2.0
+    }
+
+    fn update(&mut self, ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
+        let input_on = inputs.first().map(Signal::as_bool).unwrap_or(false);
+        let now = ctx.free_clock.as_secs_f32() * 1000.0;
+
+        if input_on != self.input_on {
+            self.history.push_back((now, input_on));
+            self.input_on = input_on;
+        }
+
+        let cutoff = now - self.time.as_ms(ctx);
+        while let Some(&(time, value)) = self.history.front() {
+            if time > cutoff {
+                break;
+            }
+            self.output_on = value;
+            self.history.pop_front();
+        }
+
+        Some(Signal::Bool(self.output_on))
+    }
+
+    fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
+        let Vec2 { x, y } = position;
+        let width = size * DELAY_WIDTH / 24.0;
+        let height = size * DELAY_HEIGHT / 24.0;
+        let left = x - width / 2.0;
+        let top = y - height / 2.0;
+
+        if is_selected {
+            draw_rectangle_lines(
+                left - 4.0 * ctx.scale,
+                top - 4.0 * ctx.scale,
+                width + 8.0 * ctx.scale,
+                height + 8.0 * ctx.scale,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
+        }
+
+        draw_rectangle(left, top, width, height, ctx.colors.bg_1);
+        draw_rectangle_lines(left, top, width, height, 1.0, ctx.colors.fg_0);
+        draw_line(x, top, x, top + height, 1.0, ctx.colors.fg_0);
+
+        // input state on the left half, delayed output on the right
+        let inset = 3.0;
+        let cell = Vec2::new(width / 2.0 - 2.0 * inset, height - 2.0 * inset);
+        if self.input_on {
+            draw_rectangle(left + inset, top + inset, cell.x, cell.y, ctx.colors.fg_2);
+        }
+        if self.output_on {
+            draw_rectangle(x + inset, top + inset, cell.x, cell.y, ctx.colors.fg_0);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.history.clear();
+        self.input_on = false;
+        self.output_on = false;
+    }
+
+    fn inspector(&mut self, ui: &mut egui::Ui) {
+        ui.label(
+            RichText::new("Delay")
+                .font(FontId::proportional(16.0))
+                .strong(),
+        );
+        ui.separator();
+
+        if let Some(time) = self.time.inspector(ui, "Time") {
+            parameter_learn_menu(&time, "Time");
+        }
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![Parameter {
+            name: "Time",
+            min: 1.0,
+            max: 10000.0,
+        }]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        if name == "Time" {
+            self.time.set_free_duration(value);
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "delay"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        self.time.save(patch);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        self.time.load(entry);
+    }
+
+    fn input_arity(&self) -> Arity {
+        Arity::Unary
+    }
+
+    fn has_output(&self) -> bool {
+        true
+    }
+
+    fn allows_feedback(&self) -> bool {
+        true
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
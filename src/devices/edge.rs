@@ -0,0 +1,271 @@
+use std::{str::FromStr, time::Duration};
+
+use egui::{FontId, RichText, Slider};
+use macroquad::{
+    math::Vec2,
+    shapes::{draw_circle, draw_circle_lines, draw_line},
+};
+
+use crate::{
+    app::DrawContext,
+    midi_learn::parameter_learn_menu,
+    patch::{DeviceEntry, PatchWriter},
+    session::UpdateContext,
+};
+
+use super::{Arity, Device, Parameter, Signal, EDGE_RADIUS};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EdgeMode {
+    Rising,
+    Falling,
+    Both,
+}
+
+impl EdgeMode {
+    fn name(&self) -> &'static str {
+        match self {
+            EdgeMode::Rising => "rising",
+            EdgeMode::Falling => "falling",
+            EdgeMode::Both => "both",
+        }
+    }
+}
+
+impl FromStr for EdgeMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rising" => Ok(EdgeMode::Rising),
+            "falling" => Ok(EdgeMode::Falling),
+            "both" => Ok(EdgeMode::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Edge {
+    position: Vec2,
+
+    mode: EdgeMode,
+
+    // if false, pulses last exactly one update
+    fixed_length: bool,
+
+    // pulse length in milliseconds if fixed length
+    duration: f32,
+
+    prev_input: bool,
+    time_remaining: Option<f32>,
+    is_on: bool,
+
+    prev_clock_time: Duration,
+}
+
+impl Edge {
+    pub fn new(position: Vec2) -> Self {
+        Edge {
+            position,
+
+            mode: EdgeMode::Rising,
+
+            fixed_length: false,
+            duration: 50.0,
+
+            prev_input: false,
+            time_remaining: None,
+            is_on: false,
+
+            prev_clock_time: Duration::ZERO,
+        }
+    }
+}
+
+impl Device for Edge {
+    fn get_position(&self) -> Vec2 {
+        self.position
+    }
+
+    fn set_position(&mut self, pos: Vec2) {
+        self.position = pos;
+    }
+
+    fn closest_border_point(&self, point: Vec2, padding: f32) -> Vec2 {
+        let delta = point - self.position;
+        self.position + delta.normalize() * (EDGE_RADIUS + padding)
+    }
+
+    fn is_point_inside(&self, pt: Vec2) -> bool {
+        self.position.distance(pt) <= EDGE_RADIUS
+    }
+
+    fn update(&mut self, ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
+        let input_on = inputs.first().map(Signal::as_bool).unwrap_or(false);
+
+        let delta_t = ctx.free_clock - self.prev_clock_time;
+        self.prev_clock_time = ctx.free_clock;
+
+        let is_edge = match self.mode {
+            EdgeMode::Rising => input_on && !self.prev_input,
+            EdgeMode::Falling => !input_on && self.prev_input,
+            EdgeMode::Both => input_on != self.prev_input,
+        };
+        self.prev_input = input_on;
+
+        if is_edge {
+            self.is_on = true;
+            self.time_remaining = self.fixed_length.then_some(self.duration);
+        } else if let Some(t_prev) = self.time_remaining {
+            let t = t_prev - delta_t.as_secs_f32() * 1000.0;
+            self.time_remaining = (t > 0.0).then_some(t);
+            self.is_on = t > 0.0;
+        } else {
+            self.is_on = false;
+        }
+
+        Some(Signal::Bool(self.is_on))
+    }
+
+    fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
+        let radius = size / 2.0;
+        let Vec2 { x, y } = position;
+
+        if is_selected {
+            draw_circle_lines(
+                x,
+                y,
+                radius + 4.0 * ctx.scale,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
+        }
+
+        if self.is_on {
+            draw_circle(x, y, radius, ctx.colors.fg_2);
+        } else {
+            draw_circle(x, y, radius, ctx.colors.bg_1);
+        }
+        draw_circle_lines(x, y, radius, 1.0, ctx.colors.fg_0);
+
+        // step symbol showing which edges are detected
+        let s = radius / 2.0;
+        let color = ctx.colors.fg_0;
+        match self.mode {
+            EdgeMode::Rising => {
+                draw_line(x - s, y + s / 2.0, x, y + s / 2.0, 1.0, color);
+                draw_line(x, y + s / 2.0, x, y - s / 2.0, 1.0, color);
+                draw_line(x, y - s / 2.0, x + s, y - s / 2.0, 1.0, color);
+            }
+            EdgeMode::Falling => {
+                draw_line(x - s, y - s / 2.0, x, y - s / 2.0, 1.0, color);
+                draw_line(x, y - s / 2.0, x, y + s / 2.0, 1.0, color);
+                draw_line(x, y + s / 2.0, x + s, y + s / 2.0, 1.0, color);
+            }
+            EdgeMode::Both => {
+                draw_line(x - s, y + s / 2.0, x - s / 2.0, y + s / 2.0, 1.0, color);
+                draw_line(
+                    x - s / 2.0,
+                    y + s / 2.0,
+                    x - s / 2.0,
+                    y - s / 2.0,
+                    1.0,
+                    color,
+                );
+                draw_line(
+                    x - s / 2.0,
+                    y - s / 2.0,
+                    x + s / 2.0,
+                    y - s / 2.0,
+                    1.0,
+                    color,
+                );
+                draw_line(
+                    x + s / 2.0,
+                    y - s / 2.0,
+                    x + s / 2.0,
+                    y + s / 2.0,
+                    1.0,
+                    color,
+                );
+                draw_line(x + s / 2.0, y + s / 2.0, x + s, y + s / 2.0, 1.0, color);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.prev_input = false;
+        self.time_remaining = None;
+        self.is_on = false;
+
+        self.prev_clock_time = Duration::ZERO;
+    }
+
+    fn inspector(&mut self, ui: &mut egui::Ui) {
+        ui.label(
+            RichText::new("Edge")
+                .font(FontId::proportional(16.0))
+                .strong(),
+        );
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.mode, EdgeMode::Rising, "Rising");
+            ui.selectable_value(&mut self.mode, EdgeMode::Falling, "Falling");
+            ui.selectable_value(&mut self.mode, EdgeMode::Both, "Both");
+        });
+
+        ui.checkbox(&mut self.fixed_length, "Fixed Length");
+        if self.fixed_length {
+            let duration = ui.add(
+                Slider::new(&mut self.duration, 1f32..=10000f32)
+                    .text("Duration")
+                    .suffix("ms"),
+            );
+            parameter_learn_menu(&duration, "Duration");
+        }
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![Parameter {
+            name: "Duration",
+            min: 1.0,
+            max: 10000.0,
+        }]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        if name == "Duration" {
+            self.duration = value.clamp(1.0, 10000.0);
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "edge"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("mode", self.mode.name());
+        patch.setting("fixed_length", self.fixed_length);
+        patch.setting("duration", self.duration);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        entry.read("mode", &mut self.mode);
+        entry.read("fixed_length", &mut self.fixed_length);
+        entry.read_clamped("duration", &mut self.duration, 1.0..=10000.0);
+    }
+
+    fn input_arity(&self) -> Arity {
+        Arity::Unary
+    }
+
+    fn has_output(&self) -> bool {
+        true
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use egui::{FontId, RichText};
 use macroquad::{
     math::Vec2,
@@ -5,9 +7,10 @@ use macroquad::{
 };
 
 use crate::app::DrawContext;
+use crate::patch::{DeviceEntry, PatchWriter};
 use crate::session::UpdateContext;
 
-use super::{Arity, Device, GATE_WIDTH};
+use super::{Arity, Device, Signal, GATE_WIDTH};
 
 #[derive(Clone, PartialEq)]
 pub enum BooleanOperation {
@@ -19,10 +22,42 @@ pub enum BooleanOperation {
     XNOR,
 }
 
+impl BooleanOperation {
+    fn name(&self) -> &'static str {
+        match self {
+            BooleanOperation::AND => "and",
+            BooleanOperation::OR => "or",
+            BooleanOperation::XOR => "xor",
+            BooleanOperation::NAND => "nand",
+            BooleanOperation::NOR => "nor",
+            BooleanOperation::XNOR => "xnor",
+        }
+    }
+}
+
+impl FromStr for BooleanOperation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "and" => Ok(BooleanOperation::AND),
+            "or" => Ok(BooleanOperation::OR),
+            "xor" => Ok(BooleanOperation::XOR),
+            "nand" => Ok(BooleanOperation::NAND),
+            "nor" => Ok(BooleanOperation::NOR),
+            "xnor" => Ok(BooleanOperation::XNOR),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Gate {
     position: Vec2,
     operation: BooleanOperation,
+
+    // output from the last update
+    is_on: bool,
 }
 
 impl Gate {
@@ -30,6 +65,8 @@ impl Gate {
         Gate {
             position,
             operation: BooleanOperation::AND,
+
+            is_on: false,
         }
     }
 }
@@ -59,7 +96,8 @@ impl Device for Gate {
         dx <= GATE_WIDTH && dy <= GATE_WIDTH
     }
 
-    fn update(&mut self, _ctx: &mut UpdateContext, inputs: Vec<bool>) -> Option<bool> {
+    fn update(&mut self, _ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
+        let inputs: Vec<bool> = inputs.iter().map(Signal::as_bool).collect();
         let out = match self.operation {
             BooleanOperation::AND => inputs.iter().fold(true, |acc, x| acc && *x),
             BooleanOperation::OR => inputs.iter().fold(false, |acc, x| acc || *x),
@@ -68,7 +106,8 @@ impl Device for Gate {
             BooleanOperation::NOR => !inputs.iter().fold(false, |acc, x| acc || *x),
             BooleanOperation::XNOR => inputs.iter().fold(false, |acc, x| acc == *x),
         };
-        Some(out)
+        self.is_on = out;
+        Some(Signal::Bool(out))
     }
 
     fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
@@ -76,24 +115,22 @@ impl Device for Gate {
 
         if is_selected {
             draw_rectangle_lines(
-                x - (size / 2.0 + 4.0),
-                y - (size / 2.0 + 4.0),
-                size + 8.0,
-                size + 8.0,
+                x - (size / 2.0 + 4.0 * ctx.scale),
+                y - (size / 2.0 + 4.0 * ctx.scale),
+                size + 8.0 * ctx.scale,
+                size + 8.0 * ctx.scale,
                 1.0,
                 ctx.colors.fg_0.with_alpha(0.5),
             );
         }
 
         draw_rectangle(x - size / 2., y - size / 2., size, size, ctx.colors.bg_1);
-        draw_rectangle_lines(
-            x - size / 2.,
-            y - size / 2.,
-            size,
-            size,
-            1.0,
-            ctx.colors.fg_0,
-        );
+        let (thickness, outline) = if self.is_on {
+            (2.0, ctx.colors.active)
+        } else {
+            (1.0, ctx.colors.fg_0)
+        };
+        draw_rectangle_lines(x - size / 2., y - size / 2., size, size, thickness, outline);
 
         draw_symbol(ctx, x, y, size * 0.5, &self.operation);
     }
@@ -145,6 +182,18 @@ impl Device for Gate {
             });
     }
 
+    fn kind(&self) -> &'static str {
+        "gate"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("operation", self.operation.name());
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        entry.read("operation", &mut self.operation);
+    }
+
     fn input_arity(&self) -> Arity {
         Arity::NAry
     }
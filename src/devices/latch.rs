@@ -8,7 +8,8 @@ use egui::{FontId, RichText};
 use crate::session::UpdateContext;
 use crate::{
     app::DrawContext,
-    devices::{Arity, Device, LATCH_RADIUS},
+    devices::{Arity, Device, Signal, LATCH_RADIUS},
+    patch::{DeviceEntry, PatchWriter},
 };
 
 #[derive(Clone)]
@@ -48,15 +49,15 @@ impl Device for Latch {
         self.position.distance(pt) <= LATCH_RADIUS
     }
 
-    fn update(&mut self, _ctx: &mut UpdateContext, inputs: Vec<bool>) -> Option<bool> {
-        let input_on = inputs.first().map(|x| *x).unwrap_or(false);
+    fn update(&mut self, _ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
+        let input_on = inputs.first().map(Signal::as_bool).unwrap_or(false);
 
         if input_on && !self.prev_input {
             self.is_on = !self.is_on;
         }
         self.prev_input = input_on;
 
-        Some(self.is_on)
+        Some(Signal::Bool(self.is_on))
     }
 
     fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
@@ -68,7 +69,7 @@ impl Device for Latch {
                 x,
                 y,
                 3,
-                radius + 4.0,
+                radius + 4.0 * ctx.scale,
                 -90.0,
                 2.0,
                 ctx.colors.fg_0.with_alpha(0.5),
@@ -79,7 +80,8 @@ impl Device for Latch {
         draw_poly(x, y, 3, radius, -90.0, ctx.colors.bg_1);
 
         if self.is_on {
-            draw_poly(x, y, 3, radius / 2.0, -90.0, ctx.colors.fg_0);
+            draw_poly_lines(x, y, 3, radius, -90.0, 2.0, ctx.colors.active);
+            draw_poly(x, y, 3, radius / 2.0, -90.0, ctx.colors.active);
         }
     }
 
@@ -98,6 +100,18 @@ impl Device for Latch {
         ui.checkbox(&mut self.is_on, "On");
     }
 
+    fn kind(&self) -> &'static str {
+        "latch"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("on", self.is_on);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        entry.read("on", &mut self.is_on);
+    }
+
     fn input_arity(&self) -> super::Arity {
         Arity::Unary
     }
@@ -1,20 +1,204 @@
-use egui::Ui;
+use egui::{DragValue, Response, Slider, Ui};
 use macroquad::prelude::Vec2;
 
-use crate::{app::DrawContext, session::UpdateContext};
+use std::{fmt, str::FromStr};
+
+use crate::{
+    app::DrawContext,
+    patch::{DeviceEntry, PatchWriter},
+    session::UpdateContext,
+};
+
+use self::subpatch::Subpatch;
 
 pub mod clock;
+pub mod counter;
+pub mod debounce;
+pub mod delay;
+pub mod edge;
 pub mod gate;
 pub mod latch;
 pub mod note;
+pub mod quantizer;
+pub mod ratchet;
+pub mod sample_hold;
+pub mod subpatch;
 pub mod trigger;
 
 const CLOCK_RADIUS: f32 = 12.0;
+const COUNTER_RADIUS: f32 = 14.0;
+const DEBOUNCE_RADIUS: f32 = 12.0;
+const DELAY_WIDTH: f32 = 32.0;
+const DELAY_HEIGHT: f32 = 16.0;
+const EDGE_RADIUS: f32 = 12.0;
 const GATE_WIDTH: f32 = 24.0;
+const INLET_RADIUS: f32 = 10.0;
 const NOTE_RADIUS: f32 = 12.0;
 const TRIGGER_RADIUS: f32 = 12.0;
+const SAMPLE_HOLD_RADIUS: f32 = 13.0;
+const QUANTIZER_RADIUS: f32 = 12.0;
+const RATCHET_RADIUS: f32 = 12.0;
+const SUBPATCH_WIDTH: f32 = 32.0;
 const LATCH_RADIUS: f32 = 12.0;
 
+/// A value carried by a wire from a device's output to another device's input
+#[derive(Clone, Copy, PartialEq)]
+pub enum Signal {
+    Bool(bool),
+    Number(f32),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SignalKind {
+    Bool,
+    Number,
+}
+
+impl fmt::Display for SignalKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignalKind::Bool => write!(f, "bool"),
+            SignalKind::Number => write!(f, "number"),
+        }
+    }
+}
+
+impl FromStr for SignalKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bool" => Ok(SignalKind::Bool),
+            "number" => Ok(SignalKind::Number),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Signal {
+    pub fn as_bool(&self) -> bool {
+        match *self {
+            Signal::Bool(b) => b,
+            Signal::Number(x) => x != 0.0,
+        }
+    }
+
+    pub fn as_number(&self) -> f32 {
+        match *self {
+            Signal::Bool(b) => b as u8 as f32,
+            Signal::Number(x) => x,
+        }
+    }
+}
+
+/// An extra single-wire input of a device, next to its main input
+pub struct AuxInput {
+    pub name: String,
+    pub kind: SignalKind,
+}
+
+impl AuxInput {
+    pub fn new(name: &str, kind: SignalKind) -> Self {
+        AuxInput {
+            name: name.to_string(),
+            kind,
+        }
+    }
+}
+
+/// A continuous device setting that can be mapped to a MIDI controller
+pub struct Parameter {
+    pub name: &'static str,
+    pub min: f32,
+    pub max: f32,
+}
+
+/// A length of time set either in milliseconds or as a fraction of a note
+/// length, for devices that wait or hold for a while
+#[derive(Clone)]
+pub struct SyncedDuration {
+    // if true, the duration is a fraction of a note length
+    bpm_sync: bool,
+
+    // duration in milliseconds if not BPM synced, from 1 to max_free_duration
+    free_duration: f32,
+    max_free_duration: f32,
+
+    // duration as fraction of note length if BPM synced
+    bpm_duration: (u32, u32),
+}
+
+impl SyncedDuration {
+    pub fn new(
+        bpm_sync: bool,
+        free_duration: f32,
+        max_free_duration: f32,
+        bpm_duration: (u32, u32),
+    ) -> Self {
+        SyncedDuration {
+            bpm_sync,
+            free_duration,
+            max_free_duration,
+            bpm_duration,
+        }
+    }
+
+    pub fn as_ms(&self, ctx: &UpdateContext) -> f32 {
+        if self.bpm_sync {
+            let (numerator, denominator) = self.bpm_duration;
+            let beats = (numerator as f32 / denominator.max(1) as f32) * 4.0;
+            let ms_per_beat = 60000.0 / ctx.bpm as f32;
+            beats * ms_per_beat
+        } else {
+            self.free_duration
+        }
+    }
+
+    pub fn set_free_duration(&mut self, ms: f32) {
+        self.free_duration = ms.clamp(1.0, self.max_free_duration);
+    }
+
+    /// Shows the settings, returning the millisecond slider if it is shown so
+    /// it can be given a MIDI learn menu
+    pub fn inspector(&mut self, ui: &mut Ui, text: &str) -> Option<Response> {
+        ui.checkbox(&mut self.bpm_sync, "BPM Sync");
+        if self.bpm_sync {
+            let (n, d) = &mut self.bpm_duration;
+            ui.horizontal(|ui| {
+                ui.label("Note Length");
+                ui.add(DragValue::new(n).range(1..=256));
+                ui.label("/");
+                ui.add(DragValue::new(d).range(1..=256));
+            });
+            None
+        } else {
+            Some(
+                ui.add(
+                    Slider::new(&mut self.free_duration, 1f32..=self.max_free_duration)
+                        .text(text)
+                        .suffix("ms"),
+                ),
+            )
+        }
+    }
+
+    pub fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("bpm_sync", self.bpm_sync);
+        patch.setting("free_duration", self.free_duration);
+        patch.fraction("bpm_duration", self.bpm_duration);
+    }
+
+    pub fn load(&mut self, entry: &DeviceEntry) {
+        entry.read("bpm_sync", &mut self.bpm_sync);
+        entry.read_clamped(
+            "free_duration",
+            &mut self.free_duration,
+            1.0..=self.max_free_duration,
+        );
+        entry.read_fraction("bpm_duration", &mut self.bpm_duration, 1..=256);
+    }
+}
+
 #[derive(PartialEq)]
 pub enum Arity {
     Nullary,
@@ -23,10 +207,14 @@ pub enum Arity {
 }
 
 pub trait Device {
-    fn update(&mut self, ctx: &mut UpdateContext, inputs: Vec<bool>) -> Option<bool>;
+    fn update(&mut self, ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal>;
     fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool);
     fn reset(&mut self) {}
 
+    // stops any notes the device is playing, which stay off until its input
+    // turns off and on again
+    fn release(&mut self) {}
+
     fn get_position(&self) -> Vec2;
     fn set_position(&mut self, pos: Vec2);
     fn closest_border_point(&self, point: Vec2, padding: f32) -> Vec2;
@@ -34,12 +222,61 @@ pub trait Device {
 
     fn inspector(&mut self, ui: &mut Ui);
 
-    // number of input wires that can be plugged into the device
+    // name the device is saved under in patches
+    fn kind(&self) -> &'static str;
+
+    // write and read back the device's settings (its position is saved
+    // separately), settings missing from a patch should be left as they are
+    fn save(&self, _patch: &mut PatchWriter) {}
+    fn load(&mut self, _entry: &DeviceEntry) {}
+
+    // settings that can be mapped to MIDI controllers, with their full ranges
+    fn parameters(&self) -> Vec<Parameter> {
+        Vec::new()
+    }
+    fn set_parameter(&mut self, _name: &str, _value: f32) {}
+
+    // number of input wires that can be plugged into the device's main input
     fn input_arity(&self) -> Arity;
 
+    // kind of signal accepted on the main input
+    fn input_kind(&self) -> SignalKind {
+        SignalKind::Bool
+    }
+
+    // extra single-wire inputs, which are plugged in on ports 1 and up
+    fn aux_inputs(&self) -> Vec<AuxInput> {
+        Vec::new()
+    }
+
+    // called before each update with the value on every aux input port
+    // (None if nothing is plugged in)
+    fn set_aux_input(&mut self, _port: usize, _value: Option<Signal>) {}
+
     // can there be wires coming out of this device?
     fn has_output(&self) -> bool;
 
+    // can wires out of this device loop back around to its inputs? they are
+    // made into feedback wires, so this should only be allowed for devices
+    // that delay their signal anyway
+    fn allows_feedback(&self) -> bool {
+        false
+    }
+
+    // kind of signal coming out of the device
+    fn output_kind(&self) -> SignalKind {
+        SignalKind::Bool
+    }
+
+    // lets the session get at the inner circuit of a subpatch to edit it
+    fn as_subpatch(&self) -> Option<&Subpatch> {
+        None
+    }
+
+    fn as_subpatch_mut(&mut self) -> Option<&mut Subpatch> {
+        None
+    }
+
     // need this so we can copy and paste devices in the session
     fn clone_dyn(&self) -> Box<dyn Device>;
 }
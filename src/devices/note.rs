@@ -1,12 +1,18 @@
+use std::str::FromStr;
+
 use egui::{DragValue, FontId, RichText};
 use macroquad::{math::Vec2, shapes::draw_hexagon};
 
 use crate::{
-    app::DrawContext, midi::MidiEventSender, session::UpdateContext,
+    app::DrawContext,
+    midi::{stable_port_name, MidiDestination, MidiEventSender, Transpose},
+    midi_learn::parameter_learn_menu,
+    patch::{DeviceEntry, PatchWriter},
+    session::UpdateContext,
     widgets::note_picker::NotePicker,
 };
 
-use super::{Arity, Device, NOTE_RADIUS};
+use super::{Arity, AuxInput, Device, Parameter, Signal, SignalKind, NOTE_RADIUS};
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum PitchClass {
@@ -43,18 +49,80 @@ impl ToString for PitchClass {
     }
 }
 
+impl FromStr for PitchClass {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            PitchClass::C,
+            PitchClass::Cs,
+            PitchClass::D,
+            PitchClass::Ds,
+            PitchClass::E,
+            PitchClass::F,
+            PitchClass::Fs,
+            PitchClass::G,
+            PitchClass::Gs,
+            PitchClass::A,
+            PitchClass::As,
+            PitchClass::B,
+        ]
+        .into_iter()
+        .find(|p| p.to_string() == s)
+        .ok_or(())
+    }
+}
+
+/// The aux inputs of a note
+#[derive(Clone, Copy)]
+enum NoteInput {
+    Accent,
+    Velocity,
+    Pitch,
+}
+
+// aux inputs in port order, starting from port 1
+const NOTE_INPUTS: [NoteInput; 3] = [NoteInput::Accent, NoteInput::Velocity, NoteInput::Pitch];
+
+impl NoteInput {
+    fn aux_input(&self) -> AuxInput {
+        match self {
+            NoteInput::Accent => AuxInput::new("Accent", SignalKind::Bool),
+            NoteInput::Velocity => AuxInput::new("Velocity", SignalKind::Number),
+            NoteInput::Pitch => AuxInput::new("Pitch", SignalKind::Number),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Note {
     position: Vec2,
 
+    destination: MidiDestination,
     midi_channel: u8,
     octave: u8,
     pitch_class: PitchClass,
     velocity: u8,
 
+    // velocity used instead while the accent input is on
+    accent_velocity: u8,
+    accent_on: bool,
+
+    // velocity coming in on the velocity input, overrides both of the above
+    velocity_input: Option<u8>,
+
+    // key coming in on the pitch input, overrides octave and pitch class
+    pitch_input: Option<u8>,
+
     event_sender: MidiEventSender,
 
-    is_on: bool,
+    // port, channel and key of the note-on that was actually sent, so the
+    // matching note-off still goes out correctly if any of them change
+    sounding: Option<(MidiDestination, u8, u8)>,
+
+    // set when the note is released while the input is still on, so it isn't
+    // played again until the input turns off
+    released: bool,
 }
 
 impl Note {
@@ -62,53 +130,77 @@ impl Note {
         Note {
             position,
 
+            destination: MidiDestination::AllPorts,
             midi_channel: 0,
             octave: 4,
             pitch_class: PitchClass::C,
             velocity: 100,
 
+            accent_velocity: 127,
+            accent_on: false,
+
+            velocity_input: None,
+            pitch_input: None,
+
             event_sender,
 
-            is_on: false,
+            sounding: None,
+            released: false,
         }
     }
 
     fn midi_key(&self) -> u8 {
-        self.pitch_class as u8 + self.octave * 12
+        self.pitch_input
+            .unwrap_or(self.pitch_class as u8 + self.octave * 12)
     }
 
-    fn turn_on(&mut self) {
-        if self.is_on {
+    fn turn_on(&mut self, transpose: &Transpose) {
+        if self.sounding.is_some() {
+            return;
+        }
+
+        let key = self.midi_key() as i32 + transpose.offset(self.midi_channel);
+        if !(0..=127).contains(&key) {
+            // transposed out of MIDI range, nothing to play
             return;
         }
+        let key = key as u8;
+
+        let velocity = if let Some(velocity) = self.velocity_input {
+            velocity
+        } else if self.accent_on {
+            self.accent_velocity
+        } else {
+            self.velocity
+        };
 
         let event = (
+            self.destination.clone(),
             self.midi_channel.into(),
             midly::MidiMessage::NoteOn {
-                key: self.midi_key().into(),
-                vel: self.velocity.into(),
+                key: key.into(),
+                vel: velocity.into(),
             },
         );
         self.event_sender.send(event);
 
-        self.is_on = true;
+        self.sounding = Some((self.destination.clone(), self.midi_channel, key));
     }
 
     fn turn_off(&mut self) {
-        if !self.is_on {
+        let Some((destination, channel, key)) = self.sounding.take() else {
             return;
-        }
+        };
 
         let event = (
-            self.midi_channel.into(),
+            destination,
+            channel.into(),
             midly::MidiMessage::NoteOff {
-                key: self.midi_key().into(),
+                key: key.into(),
                 vel: self.velocity.into(),
             },
         );
         self.event_sender.send(event);
-
-        self.is_on = false;
     }
 }
 
@@ -136,20 +228,18 @@ impl Device for Note {
         self.position.distance(pt) <= NOTE_RADIUS
     }
 
-    fn update(&mut self, ctx: &mut UpdateContext, inputs: Vec<bool>) -> Option<bool> {
+    fn update(&mut self, ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
         if ctx.is_paused {
             self.turn_off();
             return None;
         }
 
-        if let Some(input_on) = inputs.first() {
-            if *input_on {
-                self.turn_on();
-            } else {
-                self.turn_off();
-            }
-        } else {
+        let input_on = inputs.first().is_some_and(Signal::as_bool);
+        if !input_on {
+            self.released = false;
             self.turn_off();
+        } else if !self.released {
+            self.turn_on(&ctx.transpose);
         }
         None
     }
@@ -162,7 +252,7 @@ impl Device for Note {
             draw_hexagon(
                 x,
                 y,
-                radius + 4.0,
+                radius + 4.0 * ctx.scale,
                 1.0,
                 false,
                 ctx.colors.fg_0,
@@ -172,7 +262,7 @@ impl Device for Note {
 
         draw_hexagon(x, y, radius, 1.0, false, ctx.colors.fg_0, ctx.colors.bg_1);
 
-        if self.is_on {
+        if self.sounding.is_some() {
             draw_hexagon(
                 x,
                 y,
@@ -189,6 +279,13 @@ impl Device for Note {
         self.turn_off();
     }
 
+    fn release(&mut self) {
+        if self.sounding.is_some() {
+            self.turn_off();
+            self.released = true;
+        }
+    }
+
     fn inspector(&mut self, ui: &mut egui::Ui) {
         let mut octave = self.octave;
         let mut pitch = self.pitch_class;
@@ -220,6 +317,9 @@ impl Device for Note {
         ui.add_space(2.0);
 
         ui.add(NotePicker::new(&mut pitch));
+        if self.pitch_input.is_some() {
+            ui.label("Pitch is set by the Pitch input");
+        }
 
         if self.octave != octave || self.pitch_class != pitch {
             self.turn_off();
@@ -227,7 +327,11 @@ impl Device for Note {
             self.pitch_class = pitch;
         }
 
-        ui.add(egui::Slider::new(&mut self.velocity, 0..=127).text("Velocity"));
+        let velocity = ui.add(egui::Slider::new(&mut self.velocity, 0..=127).text("Velocity"));
+        parameter_learn_menu(&velocity, "Velocity");
+        let accent_velocity =
+            ui.add(egui::Slider::new(&mut self.accent_velocity, 0..=127).text("Accent Velocity"));
+        parameter_learn_menu(&accent_velocity, "Accent Velocity");
 
         ui.add_space(2.0);
 
@@ -235,12 +339,110 @@ impl Device for Note {
             ui.label("MIDI Channel");
             ui.add(DragValue::new(&mut self.midi_channel).range(0..=15));
         });
+
+        ui.horizontal(|ui| {
+            ui.label("MIDI Port");
+
+            let selected_text = match &self.destination {
+                MidiDestination::AllPorts => "All Ports".to_string(),
+                MidiDestination::Port(name) => name.clone(),
+            };
+            egui::ComboBox::from_id_salt("midi_port")
+                .selected_text(selected_text)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut self.destination,
+                        MidiDestination::AllPorts,
+                        "All Ports",
+                    );
+                    for name in self.event_sender.output_names() {
+                        ui.selectable_value(
+                            &mut self.destination,
+                            MidiDestination::Port(name.clone()),
+                            name,
+                        );
+                    }
+                });
+        });
+
+        if let MidiDestination::Port(name) = &self.destination {
+            if !self.event_sender.output_names().contains(name) {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    "This port isn't open, so nothing is being sent",
+                );
+            }
+        }
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter {
+                name: "Velocity",
+                min: 0.0,
+                max: 127.0,
+            },
+            Parameter {
+                name: "Accent Velocity",
+                min: 0.0,
+                max: 127.0,
+            },
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        let velocity = value.round().clamp(0.0, 127.0) as u8;
+        match name {
+            "Velocity" => self.velocity = velocity,
+            "Accent Velocity" => self.accent_velocity = velocity,
+            _ => {}
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "note"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        if let MidiDestination::Port(name) = &self.destination {
+            patch.setting("port", name);
+        }
+        patch.setting("channel", self.midi_channel);
+        patch.setting("octave", self.octave);
+        patch.setting("pitch", self.pitch_class.to_string());
+        patch.setting("velocity", self.velocity);
+        patch.setting("accent_velocity", self.accent_velocity);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        // older patches saved ports along with their ALSA numbers
+        if let Some(name) = entry.read_string("port") {
+            self.destination = MidiDestination::Port(stable_port_name(&name));
+        }
+        entry.read_clamped("channel", &mut self.midi_channel, 0..=15);
+        entry.read_clamped("octave", &mut self.octave, 0..=8);
+        entry.read("pitch", &mut self.pitch_class);
+        entry.read_clamped("velocity", &mut self.velocity, 0..=127);
+        entry.read_clamped("accent_velocity", &mut self.accent_velocity, 0..=127);
     }
 
     fn input_arity(&self) -> Arity {
         Arity::Unary
     }
 
+    fn aux_inputs(&self) -> Vec<AuxInput> {
+        NOTE_INPUTS.iter().map(NoteInput::aux_input).collect()
+    }
+
+    fn set_aux_input(&mut self, port: usize, value: Option<Signal>) {
+        match port.checked_sub(1).and_then(|i| NOTE_INPUTS.get(i)) {
+            Some(NoteInput::Accent) => self.accent_on = value.is_some_and(|v| v.as_bool()),
+            Some(NoteInput::Velocity) => self.velocity_input = value.map(to_midi_value),
+            Some(NoteInput::Pitch) => self.pitch_input = value.map(to_midi_value),
+            None => {}
+        }
+    }
+
     fn has_output(&self) -> bool {
         false
     }
@@ -249,3 +451,8 @@ impl Device for Note {
         Box::new(self.clone())
     }
 }
+
+// clamps a numeric signal to the range of a MIDI key or velocity
+fn to_midi_value(signal: Signal) -> u8 {
+    signal.as_number().round().clamp(0.0, 127.0) as u8
+}
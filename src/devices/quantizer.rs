@@ -0,0 +1,222 @@
+use std::str::FromStr;
+
+use egui::{FontId, RichText};
+use macroquad::{
+    math::Vec2,
+    shapes::{draw_line, draw_poly, draw_poly_lines},
+};
+
+use crate::{
+    app::DrawContext,
+    patch::{DeviceEntry, PatchWriter},
+    session::UpdateContext,
+    widgets::note_picker::NotePicker,
+};
+
+use super::{note::PitchClass, Arity, Device, Signal, SignalKind, QUANTIZER_RADIUS};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scale {
+    Chromatic,
+    Major,
+    Minor,
+    Dorian,
+    MajorPentatonic,
+    MinorPentatonic,
+}
+
+impl Scale {
+    const ALL: [Scale; 6] = [
+        Scale::Chromatic,
+        Scale::Major,
+        Scale::Minor,
+        Scale::Dorian,
+        Scale::MajorPentatonic,
+        Scale::MinorPentatonic,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Scale::Chromatic => "Chromatic",
+            Scale::Major => "Major",
+            Scale::Minor => "Minor",
+            Scale::Dorian => "Dorian",
+            Scale::MajorPentatonic => "Major Pentatonic",
+            Scale::MinorPentatonic => "Minor Pentatonic",
+        }
+    }
+
+    // semitones above the root that are in the scale
+    fn degrees(&self) -> &'static [i32] {
+        match self {
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+        }
+    }
+}
+
+impl FromStr for Scale {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scale::ALL
+            .into_iter()
+            .find(|scale| scale.name() == s)
+            .ok_or(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Quantizer {
+    position: Vec2,
+
+    root: PitchClass,
+    scale: Scale,
+
+    value: Option<f32>,
+}
+
+impl Quantizer {
+    pub fn new(position: Vec2) -> Self {
+        Quantizer {
+            position,
+
+            root: PitchClass::C,
+            scale: Scale::Major,
+
+            value: None,
+        }
+    }
+
+    /// Snaps a value to the closest key in the scale, going down on ties
+    fn quantize(&self, value: f32) -> f32 {
+        let key = value.round() as i32;
+        let root = self.root as i32;
+        let in_scale = |k: &i32| self.scale.degrees().contains(&(k - root).rem_euclid(12));
+
+        (0..12)
+            .flat_map(|d| [key - d, key + d])
+            .find(in_scale)
+            .unwrap_or(key) as f32
+    }
+}
+
+impl Device for Quantizer {
+    fn get_position(&self) -> Vec2 {
+        self.position
+    }
+
+    fn set_position(&mut self, pos: Vec2) {
+        self.position = pos;
+    }
+
+    fn closest_border_point(&self, point: Vec2, padding: f32) -> Vec2 {
+        let delta = point - self.position;
+        self.position + delta.normalize() * (QUANTIZER_RADIUS + padding)
+    }
+
+    fn is_point_inside(&self, pt: Vec2) -> bool {
+        self.position.distance(pt) <= QUANTIZER_RADIUS
+    }
+
+    fn update(&mut self, _ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
+        self.value = inputs.first().map(|x| self.quantize(x.as_number()));
+        self.value.map(Signal::Number)
+    }
+
+    fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
+        let Vec2 { x, y } = position;
+        let radius = size / 2.0;
+
+        if is_selected {
+            draw_poly_lines(
+                x,
+                y,
+                8,
+                radius + 4.0 * ctx.scale,
+                22.5,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
+        }
+
+        draw_poly(x, y, 8, radius, 22.5, ctx.colors.bg_1);
+        draw_poly_lines(x, y, 8, radius, 22.5, 1.0, ctx.colors.fg_0);
+
+        // staircase symbol
+        let step = radius / 3.0;
+        for i in 0..3 {
+            let left = x - radius / 2.0 + i as f32 * step;
+            let bottom = y + radius / 2.0 - i as f32 * step;
+            draw_line(left, bottom, left, bottom - step, 1.0, ctx.colors.fg_0);
+            draw_line(
+                left,
+                bottom - step,
+                left + step,
+                bottom - step,
+                1.0,
+                ctx.colors.fg_0,
+            );
+        }
+    }
+
+    fn inspector(&mut self, ui: &mut egui::Ui) {
+        ui.label(
+            RichText::new("Quantizer")
+                .font(FontId::proportional(16.0))
+                .strong(),
+        );
+        ui.separator();
+
+        ui.label("Root");
+        ui.add(NotePicker::new(&mut self.root));
+
+        ui.add_space(2.0);
+
+        egui::ComboBox::from_label("Scale")
+            .selected_text(self.scale.name())
+            .show_ui(ui, |ui| {
+                for scale in Scale::ALL {
+                    ui.selectable_value(&mut self.scale, scale, scale.name());
+                }
+            });
+    }
+
+    fn kind(&self) -> &'static str {
+        "quantizer"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("root", self.root.to_string());
+        patch.setting("scale", self.scale.name());
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        entry.read("root", &mut self.root);
+        entry.read("scale", &mut self.scale);
+    }
+
+    fn input_arity(&self) -> Arity {
+        Arity::Unary
+    }
+
+    fn input_kind(&self) -> SignalKind {
+        SignalKind::Number
+    }
+
+    fn has_output(&self) -> bool {
+        true
+    }
+
+    fn output_kind(&self) -> SignalKind {
+        SignalKind::Number
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
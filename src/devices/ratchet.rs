@@ -0,0 +1,248 @@
+use std::time::Duration;
+
+use egui::{DragValue, FontId, RichText, Slider};
+use macroquad::{
+    math::Vec2,
+    shapes::{draw_arc, draw_circle, draw_circle_lines},
+};
+
+use crate::{
+    app::DrawContext,
+    midi_learn::parameter_learn_menu,
+    patch::{DeviceEntry, PatchWriter},
+    session::UpdateContext,
+};
+
+use super::{Arity, Device, Parameter, Signal, SyncedDuration, RATCHET_RADIUS};
+
+#[derive(Clone)]
+pub struct Ratchet {
+    position: Vec2,
+
+    // number of pulses fired on each rising edge
+    count: u32,
+
+    // length of the window the pulses are spread over
+    window: SyncedDuration,
+
+    // what proportion of each pulse's slot it is on for (value from 0 to 1)
+    gate: f32,
+
+    // 0 spaces pulses evenly, positive values make them speed up through the
+    // window and negative values make them slow down (value from -1 to 1)
+    curve: f32,
+
+    prev_input: bool,
+
+    // milliseconds since the burst started and its total length, if firing
+    burst: Option<(f32, f32)>,
+    is_on: bool,
+
+    prev_clock_time: Duration,
+}
+
+impl Ratchet {
+    pub fn new(position: Vec2) -> Self {
+        Ratchet {
+            position,
+
+            count: 4,
+
+            window: SyncedDuration::new(true, 250.0, 10000.0, (1, 4)),
+
+            gate: 0.5,
+            curve: 0.0,
+
+            prev_input: false,
+
+            burst: None,
+            is_on: false,
+
+            prev_clock_time: Duration::ZERO,
+        }
+    }
+
+    // maps an evenly spaced position in the window (0 to 1) to where the pulse
+    // there actually starts, according to the spacing curve
+    fn warp(&self, x: f32) -> f32 {
+        x.powf(2f32.powf(-2.0 * self.curve))
+    }
+
+    fn is_pulse_on(&self, progress: f32) -> bool {
+        (0..self.count).any(|i| {
+            let start = self.warp(i as f32 / self.count as f32);
+            let end = self.warp((i + 1) as f32 / self.count as f32);
+            progress >= start && progress < start + (end - start) * self.gate
+        })
+    }
+}
+
+impl Device for Ratchet {
+    fn get_position(&self) -> Vec2 {
+        self.position
+    }
+
+    fn set_position(&mut self, pos: Vec2) {
+        self.position = pos;
+    }
+
+    fn closest_border_point(&self, point: Vec2, padding: f32) -> Vec2 {
+        let delta = point - self.position;
+        self.position + delta.normalize() * (RATCHET_RADIUS + padding)
+    }
+
+    fn is_point_inside(&self, pt: Vec2) -> bool {
+        self.position.distance(pt) <= RATCHET_RADIUS
+    }
+
+    fn update(&mut self, ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
+        let input_on = inputs.first().map(Signal::as_bool).unwrap_or(false);
+
+        let delta_t = ctx.free_clock - self.prev_clock_time;
+        self.prev_clock_time = ctx.free_clock;
+
+        if input_on && !self.prev_input {
+            self.burst = Some((0.0, self.window.as_ms(ctx)));
+        } else if let Some((elapsed, window)) = self.burst {
+            let elapsed = elapsed + delta_t.as_secs_f32() * 1000.0;
+            self.burst = (elapsed < window).then_some((elapsed, window));
+        }
+        self.prev_input = input_on;
+
+        self.is_on = match self.burst {
+            Some((elapsed, window)) => self.is_pulse_on(elapsed / window),
+            None => false,
+        };
+
+        Some(Signal::Bool(self.is_on))
+    }
+
+    fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
+        let radius = size / 2.0;
+        let Vec2 { x, y } = position;
+
+        if is_selected {
+            draw_circle_lines(
+                x,
+                y,
+                radius + 4.0 * ctx.scale,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
+        }
+
+        draw_circle(x, y, radius, ctx.colors.bg_1);
+        draw_circle_lines(x, y, radius, 1.0, ctx.colors.fg_0);
+        draw_circle_lines(x, y, radius * 0.6, 1.0, ctx.colors.fg_0);
+
+        if let Some((elapsed, window)) = self.burst {
+            draw_arc(
+                x,
+                y,
+                32,
+                radius * 0.6,
+                -90.0,
+                radius * 0.4,
+                360.0 * elapsed / window,
+                ctx.colors.fg_2,
+            );
+        }
+
+        if self.is_on {
+            draw_circle(x, y, radius * 0.4, ctx.colors.fg_0);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.prev_input = false;
+        self.burst = None;
+        self.is_on = false;
+
+        self.prev_clock_time = Duration::ZERO;
+    }
+
+    fn inspector(&mut self, ui: &mut egui::Ui) {
+        ui.label(
+            RichText::new("Ratchet")
+                .font(FontId::proportional(16.0))
+                .strong(),
+        );
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Pulses");
+            ui.add(DragValue::new(&mut self.count).range(1..=64));
+        });
+
+        ui.label("Window");
+        if let Some(length) = self.window.inspector(ui, "Length") {
+            parameter_learn_menu(&length, "Length");
+        }
+
+        let gate = ui.add(Slider::new(&mut self.gate, 0f32..=1.0f32).text("Gate"));
+        parameter_learn_menu(&gate, "Gate");
+        let curve = ui
+            .add(Slider::new(&mut self.curve, -1f32..=1.0f32).text("Curve"))
+            .on_hover_text("Positive values speed pulses up, negative values slow them down");
+        parameter_learn_menu(&curve, "Curve");
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter {
+                name: "Length",
+                min: 1.0,
+                max: 10000.0,
+            },
+            Parameter {
+                name: "Gate",
+                min: 0.0,
+                max: 1.0,
+            },
+            Parameter {
+                name: "Curve",
+                min: -1.0,
+                max: 1.0,
+            },
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "Length" => self.window.set_free_duration(value),
+            "Gate" => self.gate = value.clamp(0.0, 1.0),
+            "Curve" => self.curve = value.clamp(-1.0, 1.0),
+            _ => {}
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "ratchet"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("count", self.count);
+        self.window.save(patch);
+        patch.setting("gate", self.gate);
+        patch.setting("curve", self.curve);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        entry.read_clamped("count", &mut self.count, 1..=64);
+        self.window.load(entry);
+        entry.read_clamped("gate", &mut self.gate, 0.0..=1.0);
+        entry.read_clamped("curve", &mut self.curve, -1.0..=1.0);
+    }
+
+    fn input_arity(&self) -> Arity {
+        Arity::Unary
+    }
+
+    fn has_output(&self) -> bool {
+        true
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,195 @@
+use egui::{DragValue, FontId, RichText};
+use macroquad::{
+    math::Vec2,
+    rand::gen_range,
+    shapes::{draw_poly, draw_poly_lines},
+    text::{draw_text, measure_text},
+};
+
+use crate::{
+    app::DrawContext,
+    midi_learn::parameter_learn_menu,
+    patch::{DeviceEntry, PatchWriter},
+    session::UpdateContext,
+};
+
+use super::{Arity, Device, Parameter, Signal, SignalKind, SAMPLE_HOLD_RADIUS};
+
+#[derive(Clone)]
+pub struct SampleHold {
+    position: Vec2,
+
+    // range random values are picked from
+    min: f32,
+    max: f32,
+
+    // if true, sampled values are rounded to whole numbers (e.g. MIDI keys)
+    whole_numbers: bool,
+
+    value: f32,
+    prev_input: bool,
+}
+
+impl SampleHold {
+    pub fn new(position: Vec2) -> Self {
+        SampleHold {
+            position,
+
+            min: 48.0,
+            max: 72.0,
+
+            whole_numbers: true,
+
+            value: 60.0,
+            prev_input: false,
+        }
+    }
+
+    fn sample(&mut self) {
+        let (low, high) = (self.min.min(self.max), self.min.max(self.max));
+        let value = gen_range(low, high);
+        self.value = if self.whole_numbers {
+            value.round()
+        } else {
+            value
+        };
+    }
+}
+
+impl Device for SampleHold {
+    fn get_position(&self) -> Vec2 {
+        self.position
+    }
+
+    fn set_position(&mut self, pos: Vec2) {
+        self.position = pos;
+    }
+
+    fn closest_border_point(&self, point: Vec2, padding: f32) -> Vec2 {
+        let delta = point - self.position;
+        self.position + delta.normalize() * (SAMPLE_HOLD_RADIUS + padding)
+    }
+
+    fn is_point_inside(&self, pt: Vec2) -> bool {
+        self.position.distance(pt) <= SAMPLE_HOLD_RADIUS
+    }
+
+    fn update(&mut self, _ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
+        let input_on = inputs.first().map(Signal::as_bool).unwrap_or(false);
+
+        if input_on && !self.prev_input {
+            self.sample();
+        }
+        self.prev_input = input_on;
+
+        Some(Signal::Number(self.value))
+    }
+
+    fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
+        let Vec2 { x, y } = position;
+        let radius = size * SAMPLE_HOLD_RADIUS / 24.0;
+
+        if is_selected {
+            draw_poly_lines(
+                x,
+                y,
+                5,
+                radius + 4.0 * ctx.scale,
+                -90.0,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
+        }
+
+        draw_poly(x, y, 5, radius, -90.0, ctx.colors.bg_1);
+        draw_poly_lines(x, y, 5, radius, -90.0, 1.0, ctx.colors.fg_0);
+
+        let text = format!("{}", self.value.round());
+        let font_size = (12.0 * size / 24.0) as u16;
+        let dims = measure_text(&text, None, font_size, 1.0);
+        draw_text(
+            &text,
+            x - dims.width / 2.0,
+            y + dims.offset_y / 2.0,
+            font_size as f32,
+            ctx.colors.fg_0,
+        );
+    }
+
+    fn inspector(&mut self, ui: &mut egui::Ui) {
+        ui.label(
+            RichText::new("Sample & Hold")
+                .font(FontId::proportional(16.0))
+                .strong(),
+        );
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Range");
+            let min = ui.add(DragValue::new(&mut self.min).speed(0.1));
+            parameter_learn_menu(&min, "Min");
+            ui.label("to");
+            let max = ui.add(DragValue::new(&mut self.max).speed(0.1));
+            parameter_learn_menu(&max, "Max");
+        });
+        ui.checkbox(&mut self.whole_numbers, "Whole Numbers");
+
+        ui.label(format!("Value: {}", self.value));
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter {
+                name: "Min",
+                min: 0.0,
+                max: 127.0,
+            },
+            Parameter {
+                name: "Max",
+                min: 0.0,
+                max: 127.0,
+            },
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "Min" => self.min = value,
+            "Max" => self.max = value,
+            _ => {}
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "sample_hold"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("min", self.min);
+        patch.setting("max", self.max);
+        patch.setting("whole_numbers", self.whole_numbers);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        // any value can be typed in, as long as it's a number
+        entry.read_clamped("min", &mut self.min, f32::MIN..=f32::MAX);
+        entry.read_clamped("max", &mut self.max, f32::MIN..=f32::MAX);
+        entry.read("whole_numbers", &mut self.whole_numbers);
+    }
+
+    fn input_arity(&self) -> Arity {
+        Arity::Unary
+    }
+
+    fn has_output(&self) -> bool {
+        true
+    }
+
+    fn output_kind(&self) -> SignalKind {
+        SignalKind::Number
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+
+use egui::{FontId, RichText};
+use macroquad::{
+    math::Vec2,
+    shapes::{draw_poly, draw_poly_lines, draw_rectangle, draw_rectangle_lines},
+    text::draw_text,
+};
+
+use crate::{
+    app::DrawContext,
+    comment::Comment,
+    dag::{Dag, DeviceId},
+    patch::{DeviceEntry, PatchWriter},
+    session::{evaluate_circuit, Labels, UpdateContext},
+};
+
+use super::{Arity, AuxInput, Device, Signal, SignalKind, INLET_RADIUS, SUBPATCH_WIDTH};
+
+// more inputs than any subpatch would have, so a broken patch can't make the
+// input number overflow
+const MAX_INLET_INDEX: usize = 1023;
+
+// inner circuit and devices of a subpatch
+type Contents<'a> = (&'a Dag, &'a mut HashMap<DeviceId, Box<dyn Device>>);
+
+/// A device made out of a whole circuit of other devices. Wires coming into
+/// the subpatch are plugged into its aux inputs, and each of those feeds an
+/// Inlet device inside. The output is the output of one of the inner devices.
+pub struct Subpatch {
+    position: Vec2,
+
+    pub devices: HashMap<DeviceId, Box<dyn Device>>,
+    pub circuit: Dag,
+    pub labels: Labels,
+    pub comments: Vec<Comment>,
+
+    // inner Inlet device for each aux input, in port order
+    inlets: Vec<(DeviceId, SignalKind)>,
+
+    // inner device whose output is the subpatch's output, and its kind
+    outlet: Option<(DeviceId, SignalKind)>,
+
+    inputs: Vec<Option<Signal>>,
+    prev_outputs: HashMap<DeviceId, Signal>,
+}
+
+impl Subpatch {
+    pub fn new(
+        position: Vec2,
+        devices: HashMap<DeviceId, Box<dyn Device>>,
+        circuit: Dag,
+        inlets: Vec<(DeviceId, SignalKind)>,
+        outlet: Option<(DeviceId, SignalKind)>,
+    ) -> Self {
+        Subpatch {
+            position,
+
+            devices,
+            circuit,
+            labels: HashMap::new(),
+            comments: Vec::new(),
+
+            inputs: vec![None; inlets.len()],
+            inlets,
+            outlet,
+
+            prev_outputs: HashMap::new(),
+        }
+    }
+
+    /// Outputs of the inner devices from the last update
+    pub fn outputs(&self) -> &HashMap<DeviceId, Signal> {
+        &self.prev_outputs
+    }
+
+    /// Inner Inlet device for each input, in port order
+    pub fn inlets(&self) -> &[(DeviceId, SignalKind)] {
+        &self.inlets
+    }
+
+    /// Makes an inner Inlet device the one for input `index`, adding the
+    /// input if it's past the last one
+    pub fn set_inlet(&mut self, index: usize, id: DeviceId, kind: SignalKind) {
+        match self.inlets.get_mut(index) {
+            Some(inlet) => *inlet = (id, kind),
+            None => {
+                self.inlets.push((id, kind));
+                self.inputs.push(None);
+            }
+        }
+    }
+
+    pub fn outlet(&self) -> Option<(DeviceId, SignalKind)> {
+        self.outlet
+    }
+
+    pub fn set_outlet(&mut self, outlet: Option<(DeviceId, SignalKind)>) {
+        self.outlet = outlet;
+    }
+
+    /// Passes the values on the inputs to the inlets and evaluates the inner
+    /// circuit with `evaluate`. While the subpatch is open for editing its
+    /// contents are kept by the session, which passes them in as `contents`.
+    pub fn update_with(
+        &mut self,
+        ctx: &mut UpdateContext,
+        contents: Option<Contents>,
+        evaluate: impl FnOnce(
+            &Dag,
+            &mut HashMap<DeviceId, Box<dyn Device>>,
+            &HashMap<DeviceId, Signal>,
+            &mut UpdateContext,
+        ) -> HashMap<DeviceId, Signal>,
+    ) -> Option<Signal> {
+        let (circuit, devices) = match contents {
+            Some(contents) => contents,
+            None => (&self.circuit, &mut self.devices),
+        };
+
+        for ((inlet_id, _), value) in self.inlets.iter().zip(&self.inputs) {
+            if let Some(inlet) = devices.get_mut(inlet_id) {
+                inlet.set_aux_input(1, *value);
+            }
+        }
+
+        let outputs = evaluate(circuit, devices, &self.prev_outputs, ctx);
+        let output = self.outlet.and_then(|(id, _)| outputs.get(&id).copied());
+        self.prev_outputs = outputs;
+
+        output
+    }
+}
+
+// can't be derived since the inner devices are trait objects
+impl Clone for Subpatch {
+    fn clone(&self) -> Self {
+        Subpatch {
+            position: self.position,
+
+            devices: self
+                .devices
+                .iter()
+                .map(|(id, device)| (*id, device.clone_dyn()))
+                .collect(),
+            circuit: self.circuit.clone(),
+            labels: self.labels.clone(),
+            comments: self.comments.clone(),
+
+            inlets: self.inlets.clone(),
+            outlet: self.outlet,
+
+            inputs: self.inputs.clone(),
+            prev_outputs: self.prev_outputs.clone(),
+        }
+    }
+}
+
+impl Device for Subpatch {
+    fn get_position(&self) -> Vec2 {
+        self.position
+    }
+
+    fn set_position(&mut self, pos: Vec2) {
+        self.position = pos;
+    }
+
+    fn closest_border_point(&self, point: Vec2, padding: f32) -> Vec2 {
+        let padded_width = (SUBPATCH_WIDTH / 2.0) + padding;
+        let u = f32::max(
+            (point.x - self.position.x).abs(),
+            (point.y - self.position.y).abs(),
+        );
+        if u == 0.0 {
+            return self.position;
+        }
+
+        padded_width * (point - self.position) / u + self.position
+    }
+
+    fn is_point_inside(&self, pt: Vec2) -> bool {
+        let dx = (pt.x - self.position.x).abs();
+        let dy = (pt.y - self.position.y).abs();
+        dx <= SUBPATCH_WIDTH / 2.0 && dy <= SUBPATCH_WIDTH / 2.0
+    }
+
+    fn update(&mut self, ctx: &mut UpdateContext, _inputs: Vec<Signal>) -> Option<Signal> {
+        self.update_with(ctx, None, evaluate_circuit)
+    }
+
+    fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
+        let Vec2 { x, y } = position;
+        let width = size * SUBPATCH_WIDTH / 24.0;
+        let left = x - width / 2.0;
+        let top = y - width / 2.0;
+
+        if is_selected {
+            draw_rectangle_lines(
+                left - 4.0 * ctx.scale,
+                top - 4.0 * ctx.scale,
+                width + 8.0 * ctx.scale,
+                width + 8.0 * ctx.scale,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
+        }
+
+        draw_rectangle(left, top, width, width, ctx.colors.bg_1);
+        draw_rectangle_lines(left, top, width, width, 1.0, ctx.colors.fg_0);
+        draw_rectangle_lines(
+            left + 3.0,
+            top + 3.0,
+            width - 6.0,
+            width - 6.0,
+            1.0,
+            ctx.colors.fg_0,
+        );
+    }
+
+    fn reset(&mut self) {
+        for device in self.devices.values_mut() {
+            device.reset();
+        }
+        self.prev_outputs.clear();
+    }
+
+    fn release(&mut self) {
+        for device in self.devices.values_mut() {
+            device.release();
+        }
+    }
+
+    fn inspector(&mut self, ui: &mut egui::Ui) {
+        ui.label(
+            RichText::new("Subpatch")
+                .font(FontId::proportional(16.0))
+                .strong(),
+        );
+        ui.separator();
+
+        ui.label(format!(
+            "{} inputs, {}",
+            self.inlets.len(),
+            if self.outlet.is_some() {
+                "1 output"
+            } else {
+                "no output"
+            }
+        ));
+        ui.label("Double-click to edit its contents");
+    }
+
+    fn kind(&self) -> &'static str {
+        "subpatch"
+    }
+
+    // the inlets and outlet are read back by the patch loader, since they
+    // refer to devices inside that get new ids when loaded
+    fn save(&self, patch: &mut PatchWriter) {
+        for (id, kind) in self.inlets.iter() {
+            patch.setting("inlet", format!("{} {}", id.as_u32(), kind));
+        }
+        if let Some((id, kind)) = self.outlet {
+            patch.setting("outlet", format!("{} {}", id.as_u32(), kind));
+        }
+        patch.circuit(
+            &self.devices,
+            self.circuit.wires(),
+            &self.labels,
+            &self.comments,
+        );
+    }
+
+    fn input_arity(&self) -> Arity {
+        Arity::Nullary
+    }
+
+    fn aux_inputs(&self) -> Vec<AuxInput> {
+        self.inlets
+            .iter()
+            .enumerate()
+            .map(|(i, (_, kind))| AuxInput::new(&format!("In {}", i + 1), *kind))
+            .collect()
+    }
+
+    fn set_aux_input(&mut self, port: usize, value: Option<Signal>) {
+        if let Some(input) = port.checked_sub(1).and_then(|i| self.inputs.get_mut(i)) {
+            *input = value;
+        }
+    }
+
+    fn has_output(&self) -> bool {
+        self.outlet.is_some()
+    }
+
+    fn output_kind(&self) -> SignalKind {
+        self.outlet
+            .map(|(_, kind)| kind)
+            .unwrap_or(SignalKind::Bool)
+    }
+
+    fn as_subpatch(&self) -> Option<&Subpatch> {
+        Some(self)
+    }
+
+    fn as_subpatch_mut(&mut self) -> Option<&mut Subpatch> {
+        Some(self)
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+/// Stands in for a wire coming into a subpatch from outside. The subpatch
+/// passes in the value on the matching aux input before each update.
+#[derive(Clone)]
+pub struct Inlet {
+    position: Vec2,
+    index: usize,
+    kind: SignalKind,
+
+    value: Option<Signal>,
+}
+
+impl Inlet {
+    pub fn new(position: Vec2, index: usize, kind: SignalKind) -> Self {
+        Inlet {
+            position,
+            index,
+            kind,
+
+            value: None,
+        }
+    }
+}
+
+impl Device for Inlet {
+    fn get_position(&self) -> Vec2 {
+        self.position
+    }
+
+    fn set_position(&mut self, pos: Vec2) {
+        self.position = pos;
+    }
+
+    fn closest_border_point(&self, point: Vec2, padding: f32) -> Vec2 {
+        let delta = point - self.position;
+        self.position + delta.normalize_or_zero() * (INLET_RADIUS + padding)
+    }
+
+    fn is_point_inside(&self, pt: Vec2) -> bool {
+        self.position.distance(pt) <= INLET_RADIUS
+    }
+
+    fn update(&mut self, _ctx: &mut UpdateContext, _inputs: Vec<Signal>) -> Option<Signal> {
+        self.value
+    }
+
+    fn draw(&self, ctx: &DrawContext, position: Vec2, size: f32, is_selected: bool) {
+        let Vec2 { x, y } = position;
+        let radius = size * INLET_RADIUS / 24.0;
+
+        if is_selected {
+            draw_poly_lines(
+                x,
+                y,
+                3,
+                radius + 4.0 * ctx.scale,
+                0.0,
+                1.0,
+                ctx.colors.fg_0.with_alpha(0.5),
+            );
+        }
+
+        let fill = if self.value.is_some_and(|v| v.as_bool()) {
+            ctx.colors.fg_2
+        } else {
+            ctx.colors.bg_1
+        };
+        draw_poly(x, y, 3, radius, 0.0, fill);
+        draw_poly_lines(x, y, 3, radius, 0.0, 1.0, ctx.colors.fg_0);
+
+        let text = format!("In {}", self.index + 1);
+        draw_text(
+            &text,
+            x - radius,
+            y - radius - 4.0 * ctx.scale,
+            14.0 * ctx.scale,
+            ctx.colors.fg_2,
+        );
+    }
+
+    fn inspector(&mut self, ui: &mut egui::Ui) {
+        ui.label(
+            RichText::new(format!("In {}", self.index + 1))
+                .font(FontId::proportional(16.0))
+                .strong(),
+        );
+        ui.separator();
+
+        ui.label("Outputs whatever is plugged into this input of the subpatch");
+    }
+
+    fn kind(&self) -> &'static str {
+        "inlet"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("index", self.index);
+        patch.setting("signal", self.kind);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        entry.read_clamped("index", &mut self.index, 0..=MAX_INLET_INDEX);
+        entry.read("signal", &mut self.kind);
+    }
+
+    fn input_arity(&self) -> Arity {
+        Arity::Nullary
+    }
+
+    // not listed in aux_inputs, this is only called by the enclosing subpatch
+    fn set_aux_input(&mut self, _port: usize, value: Option<Signal>) {
+        self.value = value;
+    }
+
+    fn has_output(&self) -> bool {
+        true
+    }
+
+    fn output_kind(&self) -> SignalKind {
+        self.kind
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
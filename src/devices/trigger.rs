@@ -10,7 +10,9 @@ use egui::{DragValue, FontId, RichText, Slider};
 use crate::session::UpdateContext;
 use crate::{
     app::DrawContext,
-    devices::{Arity, Device, TRIGGER_RADIUS},
+    devices::{Arity, Device, Parameter, Signal, TRIGGER_RADIUS},
+    midi_learn::parameter_learn_menu,
+    patch::{DeviceEntry, PatchWriter},
 };
 
 #[derive(Clone)]
@@ -81,8 +83,8 @@ impl Device for Trigger {
         self.position.distance(pt) <= TRIGGER_RADIUS
     }
 
-    fn update(&mut self, ctx: &mut UpdateContext, inputs: Vec<bool>) -> Option<bool> {
-        let input_on = inputs.first().map(|x| *x).unwrap_or(false);
+    fn update(&mut self, ctx: &mut UpdateContext, inputs: Vec<Signal>) -> Option<Signal> {
+        let input_on = inputs.first().map(Signal::as_bool).unwrap_or(false);
         if self.retrigger_mode {
             if input_on && self.ready_to_fire {
                 self.fire(ctx);
@@ -94,7 +96,7 @@ impl Device for Trigger {
                 // non-retrigger-mode triggers output at least one frame of false before firing
                 // again.
                 // should probably refactor at some point
-                return Some(false);
+                return Some(Signal::Bool(false));
             }
         }
 
@@ -112,9 +114,9 @@ impl Device for Trigger {
             } else {
                 self.time_remaining = None;
             }
-            Some(true)
+            Some(Signal::Bool(true))
         } else {
-            Some(false)
+            Some(Signal::Bool(false))
         }
     }
 
@@ -127,7 +129,7 @@ impl Device for Trigger {
                 x,
                 y,
                 3,
-                radius + 4.0,
+                radius + 4.0 * ctx.scale,
                 90.0,
                 1.0,
                 ctx.colors.fg_0.with_alpha(0.5),
@@ -170,14 +172,47 @@ impl Device for Trigger {
                 ui.add(DragValue::new(d).range(1..=256));
             });
         } else {
-            ui.add(
+            let duration = ui.add(
                 Slider::new(&mut self.duration, 1f32..=10000f32)
                     .text("Duration")
                     .suffix("ms"),
             );
+            parameter_learn_menu(&duration, "Duration");
         }
     }
 
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![Parameter {
+            name: "Duration",
+            min: 1.0,
+            max: 10000.0,
+        }]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        if name == "Duration" {
+            self.duration = value.clamp(1.0, 10000.0);
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "trigger"
+    }
+
+    fn save(&self, patch: &mut PatchWriter) {
+        patch.setting("duration", self.duration);
+        patch.setting("bpm_sync", self.bpm_sync);
+        patch.fraction("bpm_duration", self.bpm_duration);
+        patch.setting("retrigger_mode", self.retrigger_mode);
+    }
+
+    fn load(&mut self, entry: &DeviceEntry) {
+        entry.read_clamped("duration", &mut self.duration, 1.0..=10000.0);
+        entry.read("bpm_sync", &mut self.bpm_sync);
+        entry.read_fraction("bpm_duration", &mut self.bpm_duration, 1..=256);
+        entry.read("retrigger_mode", &mut self.retrigger_mode);
+    }
+
     fn input_arity(&self) -> super::Arity {
         Arity::Unary
     }
@@ -2,10 +2,15 @@ use egui::Color32;
 use macroquad::{
     color::Color,
     math::{vec2, Vec2},
-    shapes::{draw_line, draw_poly},
+    shapes::{draw_line, draw_poly, draw_rectangle, draw_rectangle_lines},
+    text::{draw_text, measure_text},
 };
 
-use crate::{app::DrawContext, dag::WireType, devices::Device};
+use crate::{
+    app::DrawContext,
+    dag::WireType,
+    devices::{Device, SignalKind},
+};
 
 pub struct ColorPalette {
     pub fg_0: Color,
@@ -17,6 +22,52 @@ pub struct ColorPalette {
     pub bg_2: Color,
     pub bg_3: Color,
     pub error: Color,
+
+    // wires and devices whose output is on
+    pub active: Color,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Theme {
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub fn palette(&self) -> ColorPalette {
+        match self {
+            Theme::Dark => ColorPalette {
+                fg_0: Color::from_hex(0xFFFFFF),
+                fg_1: Color::from_hex(0xE0E0E0),
+                fg_2: Color::from_hex(0xB7B7B7),
+                fg_3: Color::from_hex(0x8C8C8C),
+
+                bg_0: Color::from_hex(0x000000),
+                bg_1: Color::from_hex(0x111111),
+                bg_2: Color::from_hex(0x212121),
+                bg_3: Color::from_hex(0x3C3C3C),
+
+                error: Color::from_hex(0xF21B1B),
+
+                active: Color::from_hex(0x4CD964),
+            },
+            Theme::Light => ColorPalette {
+                fg_0: Color::from_hex(0x000000),
+                fg_1: Color::from_hex(0x1F1F1F),
+                fg_2: Color::from_hex(0x484848),
+                fg_3: Color::from_hex(0x737373),
+
+                bg_0: Color::from_hex(0xFFFFFF),
+                bg_1: Color::from_hex(0xEEEEEE),
+                bg_2: Color::from_hex(0xDEDEDE),
+                bg_3: Color::from_hex(0xC3C3C3),
+
+                error: Color::from_hex(0xD01010),
+
+                active: Color::from_hex(0x17A43B),
+            },
+        }
+    }
 }
 
 pub fn color_to_color32(c: Color) -> Color32 {
@@ -71,22 +122,101 @@ pub fn draw_arrow(
     draw_poly(arrow_pos.x, arrow_pos.y, 3, head_size, rotation, fill);
 }
 
-pub fn draw_wire(from: Vec2, to: Vec2, wire_type: WireType, color: Color) {
-    match wire_type {
-        WireType::Normal => draw_arrow(from, to, 1.5, 6.0, color, None),
-        WireType::Negated => draw_arrow(from, to, 1.5, 5.0, macroquad::color::BLACK, Some(color)),
+pub fn draw_dashed_arrow(from: Vec2, to: Vec2, head_size: f32, color: Color) {
+    let rotation = vec2(1.0, 0.0).angle_between(to - from).to_degrees();
+    let arrow_pos = to - (to - from).normalize() * head_size;
+
+    draw_dashed_line(from, arrow_pos, color, 8.0);
+    draw_poly(arrow_pos.x, arrow_pos.y, 3, head_size, rotation, color);
+}
+
+// boolean wires are drawn solid and numeric wires dashed, so the kind of
+// signal a wire carries can be told at a glance. feedback wires get a small
+// square halfway along, like the delay element in a block diagram
+pub fn draw_wire(
+    draw_ctx: &DrawContext,
+    from: Vec2,
+    to: Vec2,
+    wire_type: WireType,
+    kind: SignalKind,
+    feedback: bool,
+    color: Color,
+) {
+    let scale = draw_ctx.scale;
+    let background = draw_ctx.colors.bg_0;
+    match (kind, wire_type) {
+        (SignalKind::Number, _) => draw_dashed_arrow(from, to, 6.0 * scale, color),
+        (SignalKind::Bool, WireType::Normal) => {
+            draw_arrow(from, to, 1.5 * scale, 6.0 * scale, color, None)
+        }
+        (SignalKind::Bool, WireType::Negated) => {
+            draw_arrow(from, to, 1.5 * scale, 5.0 * scale, background, Some(color))
+        }
+    }
+
+    if feedback {
+        let mid = (from + to) / 2.0;
+        let half = 4.0 * scale;
+        draw_rectangle(
+            mid.x - half,
+            mid.y - half,
+            half * 2.0,
+            half * 2.0,
+            background,
+        );
+        draw_rectangle_lines(
+            mid.x - half,
+            mid.y - half,
+            half * 2.0,
+            half * 2.0,
+            1.5,
+            color,
+        );
     }
 }
 
+pub fn draw_port_label(draw_ctx: &DrawContext, position: Vec2, name: &str) {
+    let offset = 4.0 * draw_ctx.scale;
+    draw_text(
+        name,
+        position.x + offset,
+        position.y - offset,
+        14.0 * draw_ctx.scale,
+        draw_ctx.colors.fg_3,
+    );
+}
+
+// centered under `position`, which is the bottom of the device
+pub fn draw_device_label(draw_ctx: &DrawContext, position: Vec2, label: &str) {
+    let font_size = 14.0 * draw_ctx.scale;
+    let dims = measure_text(label, None, font_size as u16, 1.0);
+    draw_text(
+        label,
+        position.x - dims.width / 2.0,
+        position.y + dims.offset_y,
+        font_size,
+        draw_ctx.colors.fg_2,
+    );
+}
+
 pub fn draw_wire_from_device<D: Device + ?Sized>(
     draw_ctx: &DrawContext,
     from_dev: &D,
     to: Vec2,
     wire_type: WireType,
+    feedback: bool,
     color: Color,
 ) {
     let from_pos = from_dev.closest_border_point(draw_ctx.viewport_to_world(to), 3.0);
-    draw_wire(draw_ctx.world_to_viewport(from_pos), to, wire_type, color);
+    draw_wire(
+        draw_ctx,
+        draw_ctx.world_to_viewport(from_pos),
+        to,
+        wire_type,
+        from_dev.output_kind(),
+        feedback,
+        color,
+    );
 }
 
 pub fn draw_wire_between_devices<D: Device + ?Sized>(
@@ -94,14 +224,18 @@ pub fn draw_wire_between_devices<D: Device + ?Sized>(
     from_dev: &D,
     to_dev: &D,
     wire_type: WireType,
+    feedback: bool,
     color: Color,
 ) {
     let from_pos = from_dev.closest_border_point(to_dev.get_position(), 3.0);
     let to_pos = to_dev.closest_border_point(from_dev.get_position(), 3.0);
     draw_wire(
+        draw_ctx,
         draw_ctx.world_to_viewport(from_pos),
         draw_ctx.world_to_viewport(to_pos),
         wire_type,
+        from_dev.output_kind(),
+        feedback,
         color,
     );
 }
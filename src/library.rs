@@ -0,0 +1,53 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::config::config_dir;
+
+/// Patch snippets saved by the user for reuse, kept as one file per entry in
+/// the `library` folder of the config directory
+const LIBRARY_EXTENSION: &str = "graf";
+
+fn library_dir() -> io::Result<PathBuf> {
+    match config_dir() {
+        Some(dir) => Ok(dir.join("library")),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "could not find a config directory",
+        )),
+    }
+}
+
+fn entry_path(name: &str) -> io::Result<PathBuf> {
+    Ok(library_dir()?.join(format!("{}.{}", name, LIBRARY_EXTENSION)))
+}
+
+/// Names of all entries in the library, sorted alphabetically
+pub fn entries() -> Vec<String> {
+    let Ok(dir) = library_dir().and_then(fs::read_dir) else {
+        return Vec::new();
+    };
+
+    let mut names: Vec<String> = dir
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == LIBRARY_EXTENSION))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Names can't contain path separators, so entries always end up directly in
+/// the library directory
+pub fn is_valid_name(name: &str) -> bool {
+    let name = name.trim();
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+pub fn save_entry(name: &str, patch: &str) -> io::Result<()> {
+    fs::create_dir_all(library_dir()?)?;
+    fs::write(entry_path(name.trim())?, patch)
+}
+
+pub fn load_entry(name: &str) -> io::Result<String> {
+    fs::read_to_string(entry_path(name)?)
+}
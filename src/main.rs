@@ -1,13 +1,21 @@
 use app::App;
 use macroquad::prelude::*;
 
-use crate::drawing_utils::ColorPalette;
+use crate::config::AppConfig;
 
+mod analyzer;
 mod app;
+mod comment;
+mod config;
 mod dag;
 mod devices;
 mod drawing_utils;
+mod library;
 mod midi;
+mod midi_learn;
+mod midi_monitor;
+mod minimap;
+mod patch;
 mod session;
 mod widgets;
 
@@ -21,21 +29,15 @@ fn window_conf() -> Conf {
 
 #[macroquad::main(window_conf())]
 async fn main() {
-    let colors = ColorPalette {
-        fg_0: Color::from_hex(0xFFFFFF),
-        fg_1: Color::from_hex(0xE0E0E0),
-        fg_2: Color::from_hex(0xB7B7B7),
-        fg_3: Color::from_hex(0x8C8C8C),
+    let config = AppConfig::load();
 
-        bg_0: Color::from_hex(0x000000),
-        bg_1: Color::from_hex(0x111111),
-        bg_2: Color::from_hex(0x212121),
-        bg_3: Color::from_hex(0x3C3C3C),
+    let mut app = App::new(config);
 
-        error: Color::from_hex(0xF21B1B),
-    };
+    // otherwise random devices play the same sequence on every run
+    rand::srand(miniquad::date::now() as u64);
 
-    let mut app = App::new(colors);
+    // handle quitting ourselves so held notes can be released first
+    prevent_quit();
 
     loop {
         let mut egui_wants_pointer = false;
@@ -53,6 +55,11 @@ async fn main() {
         app.draw();
         egui_macroquad::draw();
 
+        if is_quit_requested() {
+            app.shutdown();
+            break;
+        }
+
         next_frame().await
     }
 }
@@ -1,81 +1,654 @@
 use core::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
 use std::rc::Rc;
-use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use midir::{MidiOutput, MidiOutputConnection, MidiOutputPort};
+use midir::{
+    ConnectErrorKind, InitError, MidiInput, MidiInputConnection, MidiInputPort, MidiOutput,
+    MidiOutputConnection, MidiOutputPort, PortInfoError, SendError,
+};
 use midly::live::LiveEvent;
-use midly::num::u4;
+use midly::num::{u4, u7};
 use midly::MidiMessage;
 
+// controller numbers of the channel mode messages sent when panicking
+pub const ALL_SOUND_OFF: u8 = 120;
+pub const ALL_NOTES_OFF: u8 = 123;
+
+// name other applications see when subscribing to graf's own virtual port
+const VIRTUAL_PORT_NAME: &str = "graf";
+
+// how often to look for ports that have disappeared or come back
+const PORT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Is this graf's own virtual port? It's left out of the port lists so graf
+/// can't be connected to itself. Ports are listed as `client:port` with ALSA,
+/// once their numbers are taken off, and by just the port name on macOS.
+fn is_own_virtual_port(name: &str) -> bool {
+    name == VIRTUAL_PORT_NAME || name == format!("{0}:{0}", VIRTUAL_PORT_NAME)
+}
+
+/// A port's name without the `client:port` numbers ALSA adds to the end,
+/// which change whenever a device is plugged back in or a program restarts.
+/// Ports are listed, matched and reconnected by this name.
+pub fn stable_port_name(name: &str) -> String {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let numbered = name.rsplit_once(' ').filter(|(_, address)| {
+        address
+            .split_once(':')
+            .is_some_and(|(client, port)| is_number(client) && is_number(port))
+    });
+    match numbered {
+        Some((name, _)) => name.to_string(),
+        None => name.to_string(),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum MidiError {
+    // the MIDI backend (e.g. the ALSA sequencer) couldn't be opened
+    Init(InitError),
+    PortInfo(PortInfoError),
+    Connect(String, ConnectErrorKind),
+    Send(String, SendError),
+}
+
+impl fmt::Display for MidiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidiError::Init(err) => write!(f, "{}", err),
+            MidiError::PortInfo(err) => write!(f, "{}", err),
+            MidiError::Connect(port, err) => write!(f, "could not connect to {}: {}", port, err),
+            MidiError::Send(port, err) => write!(f, "could not send to {}: {}", port, err),
+        }
+    }
+}
+
 // using this type alias because LiveEvents need lifetimes and I don't
 // want that to pollute my other types
-pub type MidiEvent = (u4, MidiMessage);
+pub type MidiEvent = (MidiDestination, u4, MidiMessage);
+
+// events received from MIDI inputs don't need a destination
+pub type MidiInputEvent = (u4, MidiMessage);
+
+/// Which of the open output ports a MIDI event should be sent to
+#[derive(Clone, PartialEq)]
+pub enum MidiDestination {
+    AllPorts,
+    Port(String),
+}
+
+/// Transposition applied to every note as it is sent, both globally and per
+/// MIDI channel.
+pub struct Transpose {
+    pub semitones: i32,
+    pub octaves: i32,
+    pub channels: [i32; 16],
+}
+
+impl Transpose {
+    pub fn new() -> Self {
+        Transpose {
+            semitones: 0,
+            octaves: 0,
+            channels: [0; 16],
+        }
+    }
+
+    /// Total offset in semitones for notes sent on the given channel
+    pub fn offset(&self, channel: u8) -> i32 {
+        self.semitones + self.octaves * 12 + self.channels[channel as usize % 16]
+    }
+}
 
 #[derive(Clone)]
 pub struct MidiEventSender {
-    event_queue: Rc<RefCell<VecDeque<MidiEvent>>>
+    event_queue: Rc<RefCell<VecDeque<MidiEvent>>>,
+    output_names: Rc<RefCell<Vec<String>>>,
 }
 
 impl MidiEventSender {
     pub fn send(&self, event: MidiEvent) {
         self.event_queue.borrow_mut().push_back(event);
     }
+
+    /// Names of the output ports that are currently open
+    pub fn output_names(&self) -> Vec<String> {
+        self.output_names.borrow().clone()
+    }
+}
+
+struct OutputConnection {
+    name: String,
+    connection: MidiOutputConnection,
+
+    // virtual ports are created by graf rather than found in the port list
+    is_virtual: bool,
+
+    // (channel, key) of every note that has been sent a note-on but no
+    // note-off yet, so they can be released if something goes wrong
+    held_notes: HashSet<(u4, u7)>,
+}
+
+impl OutputConnection {
+    fn send(&mut self, channel: u4, message: MidiMessage) -> Result<(), MidiError> {
+        let mut buf = Vec::new();
+        let event = LiveEvent::Midi { channel, message };
+        if event.write(&mut buf).is_err() {
+            let err = SendError::InvalidData("could not encode MIDI message");
+            return Err(MidiError::Send(self.name.clone(), err));
+        }
+        self.connection
+            .send(&buf)
+            .map_err(|err| MidiError::Send(self.name.clone(), err))?;
+
+        match message {
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                self.held_notes.insert((channel, key));
+            }
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                self.held_notes.remove(&(channel, key));
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // the note-offs that were sent are added to `sent`
+    fn release_held_notes(&mut self, sent: &mut Vec<MidiEvent>) -> Result<(), MidiError> {
+        let held: Vec<(u4, u7)> = self.held_notes.iter().copied().collect();
+        for (channel, key) in held {
+            let message = MidiMessage::NoteOff { key, vel: 0.into() };
+            self.send(channel, message)?;
+            sent.push((MidiDestination::Port(self.name.clone()), channel, message));
+        }
+        Ok(())
+    }
 }
 
 pub struct MidiConfig {
-    pub midi_out: MidiOutput,
+    // None if the MIDI backend couldn't be initialized
+    pub midi_out: Option<MidiOutput>,
     pub ports: Vec<(String, MidiOutputPort, bool)>,
-    pub connection: Option<MidiOutputConnection>,
+
+    connections: Vec<OutputConnection>,
+
+    // ports the user asked to be connected to, which get reconnected
+    // automatically if they disappear and come back
+    wanted_ports: Vec<String>,
+    last_poll: Instant,
 
     event_queue: Rc<RefCell<VecDeque<MidiEvent>>>,
+    output_names: Rc<RefCell<Vec<String>>>,
+
+    // every event taken off the queue since the last `take_sent_events`,
+    // whether or not any port received it, along with the messages sent
+    // straight to the ports when releasing held notes or panicking
+    sent_events: Vec<MidiEvent>,
 }
 
 impl MidiConfig {
-    pub fn new() -> Self {
-        let midi_out = MidiOutput::new("graf").unwrap();
-        let mut midi_cfg = MidiConfig {
-            midi_out,
+    /// Creates an unconnected config that will connect to the given ports
+    /// whenever they're available. The MIDI backend itself is opened lazily
+    /// by `refresh_ports`, so that failing to open it isn't fatal.
+    pub fn new(wanted_ports: Vec<String>) -> Self {
+        MidiConfig {
+            midi_out: None,
             ports: vec![],
-            connection: None,
+            connections: vec![],
+
+            wanted_ports,
+            last_poll: Instant::now(),
 
             event_queue: Rc::new(RefCell::new(VecDeque::new())),
+            output_names: Rc::new(RefCell::new(Vec::new())),
+
+            sent_events: Vec::new(),
+        }
+    }
+
+    pub fn refresh_ports(&mut self) -> Result<(), MidiError> {
+        if self.midi_out.is_none() {
+            self.midi_out = Some(MidiOutput::new("graf").map_err(MidiError::Init)?);
+        }
+        let Some(midi_out) = &self.midi_out else {
+            return Ok(());
         };
 
-        midi_cfg.refresh_ports();
+        self.ports.clear();
+        for port in midi_out.ports() {
+            // ports can vanish between listing and querying them, just skip those
+            if let Some(name) = midi_out
+                .port_name(&port)
+                .ok()
+                .map(|name| stable_port_name(&name))
+                .filter(|name| !is_own_virtual_port(name))
+            {
+                let connected = self.connections.iter().any(|c| c.name == name);
+                self.ports.push((name, port, connected));
+            }
+        }
 
-        midi_cfg
+        Ok(())
     }
 
-    pub fn refresh_ports(&mut self) {
-        self.ports.clear();
-        for port in self.midi_out.ports() {
-            self.ports.push((self.midi_out.port_name(&port).unwrap(), port, false));
+    pub fn is_connected(&self, port_name: &str) -> bool {
+        self.connections.iter().any(|c| c.name == port_name)
+    }
+
+    pub fn wanted_ports(&self) -> &[String] {
+        &self.wanted_ports
+    }
+
+    /// Names of all open outputs, including the virtual port
+    pub fn connected_ports(&self) -> impl Iterator<Item = &String> {
+        self.connections.iter().map(|c| &c.name)
+    }
+
+    /// Ports that were connected by the user but are currently unavailable
+    pub fn missing_ports(&self) -> impl Iterator<Item = &String> {
+        self.wanted_ports
+            .iter()
+            .filter(|name| !self.is_connected(name))
+    }
+
+    pub fn connect_to_port(&mut self, port: &MidiOutputPort) -> Result<(), MidiError> {
+        let name = match &self.midi_out {
+            Some(midi_out) => midi_out
+                .port_name(port)
+                .map(|name| stable_port_name(&name))
+                .map_err(MidiError::PortInfo)?,
+            None => return Err(MidiError::Init(InitError)),
+        };
+
+        if !self.wanted_ports.contains(&name) {
+            self.wanted_ports.push(name.clone());
+        }
+        if self.is_connected(&name) {
+            return Ok(());
+        }
+
+        let midi_conn_out = MidiOutput::new("graf-connection-output").map_err(MidiError::Init)?;
+        let connection = midi_conn_out
+            .connect(port, "graf-midi")
+            .map_err(|err| MidiError::Connect(name.clone(), err.kind()))?;
+        self.connections.push(OutputConnection {
+            name,
+            connection,
+            is_virtual: false,
+            held_notes: HashSet::new(),
+        });
+
+        self.connections_changed();
+        Ok(())
+    }
+
+    pub fn has_virtual_port(&self) -> bool {
+        self.connections.iter().any(|c| c.is_virtual)
+    }
+
+    /// Creates graf's own virtual output port, which DAWs and soft synths on
+    /// the same machine can subscribe to directly.
+    #[cfg(unix)]
+    pub fn open_virtual_port(&mut self) -> Result<(), MidiError> {
+        use midir::os::unix::VirtualOutput;
+
+        if self.has_virtual_port() {
+            return Ok(());
+        }
+
+        let name = format!("{} (virtual)", VIRTUAL_PORT_NAME);
+        let midi_conn_out = MidiOutput::new(VIRTUAL_PORT_NAME).map_err(MidiError::Init)?;
+        let connection = midi_conn_out
+            .create_virtual(VIRTUAL_PORT_NAME)
+            .map_err(|err| MidiError::Connect(name.clone(), err.kind()))?;
+        self.connections.push(OutputConnection {
+            name,
+            connection,
+            is_virtual: true,
+            held_notes: HashSet::new(),
+        });
+
+        self.connections_changed();
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub fn close_virtual_port(&mut self) -> Result<(), MidiError> {
+        let mut result = Ok(());
+        for conn in self.connections.iter_mut() {
+            if conn.is_virtual {
+                result = conn.release_held_notes(&mut self.sent_events);
+            }
+        }
+        self.connections.retain(|c| !c.is_virtual);
+
+        self.connections_changed();
+        result
+    }
+
+    pub fn disconnect_port(&mut self, port_name: &str) -> Result<(), MidiError> {
+        self.wanted_ports.retain(|name| name != port_name);
+
+        // don't leave notes hanging on the port we're closing
+        let mut result = Ok(());
+        for conn in self.connections.iter_mut() {
+            if conn.name == port_name {
+                result = conn.release_held_notes(&mut self.sent_events);
+            }
+        }
+        self.connections.retain(|c| c.name != port_name);
+
+        self.connections_changed();
+        result
+    }
+
+    /// Drops connections to ports that have disappeared and reconnects to
+    /// wanted ports that have reappeared. Only actually polls the MIDI backend
+    /// every so often, so it's fine to call this every frame.
+    pub fn maintain_connections(&mut self) -> Result<(), MidiError> {
+        // if the backend couldn't be opened, that's already been reported and
+        // it's only tried again when the user refreshes the ports
+        if self.midi_out.is_none() || self.last_poll.elapsed() < PORT_POLL_INTERVAL {
+            return Ok(());
+        }
+        self.last_poll = Instant::now();
+
+        self.refresh_ports()?;
+
+        let available: Vec<&String> = self.ports.iter().map(|(name, _, _)| name).collect();
+        let lost: Vec<String> = self
+            .connections
+            .iter()
+            .filter(|c| !c.is_virtual && !available.contains(&&c.name))
+            .map(|c| c.name.clone())
+            .collect();
+        if !lost.is_empty() {
+            self.connections.retain(|c| !lost.contains(&c.name));
+            self.connections_changed();
+        }
+
+        self.connect_wanted_ports()
+    }
+
+    /// Connects to every wanted port that's available but not connected yet.
+    /// Uses the port list from the last `refresh_ports`.
+    pub fn connect_wanted_ports(&mut self) -> Result<(), MidiError> {
+        let mut result = Ok(());
+        let available: Vec<MidiOutputPort> = self
+            .ports
+            .iter()
+            .filter(|(name, _, connected)| !connected && self.wanted_ports.contains(name))
+            .map(|(_, port, _)| port.clone())
+            .collect();
+        for port in available {
+            if let Err(err) = self.connect_to_port(&port) {
+                result = Err(err);
+            }
+        }
+
+        result
+    }
+
+    fn connections_changed(&mut self) {
+        for (name, _port, connected) in self.ports.iter_mut() {
+            *connected = self.connections.iter().any(|c| c.name == *name);
+        }
+
+        *self.output_names.borrow_mut() = self.connections.iter().map(|c| c.name.clone()).collect();
+    }
+
+    /// Sends all queued events. If a port fails it's closed, since it has
+    /// most likely gone away, and will be reconnected once it's back.
+    pub fn process_events(&mut self) -> Result<(), MidiError> {
+        let mut result = Ok(());
+        let mut failed = Vec::new();
+
+        let events: Vec<MidiEvent> = self.event_queue.borrow_mut().drain(..).collect();
+        self.sent_events.extend(events.iter().cloned());
+        for (destination, channel, message) in events {
+            for conn in self.connections.iter_mut() {
+                let routed = match &destination {
+                    MidiDestination::AllPorts => true,
+                    MidiDestination::Port(name) => conn.name == *name,
+                };
+                if routed && !failed.contains(&conn.name) {
+                    if let Err(err) = conn.send(channel, message) {
+                        failed.push(conn.name.clone());
+                        result = Err(err);
+                    }
+                }
+            }
         }
+
+        if !failed.is_empty() {
+            self.connections.retain(|c| !failed.contains(&c.name));
+            self.connections_changed();
+        }
+
+        result
     }
 
-    pub fn connect_to_port(&mut self, port: &MidiOutputPort) {
-        let midi_conn_out = MidiOutput::new("graf-connection-output").unwrap();
-        self.connection = Some(midi_conn_out.connect(port, "graf-midi").unwrap());
+    /// Events sent by the devices since this was last called, for monitoring
+    pub fn take_sent_events(&mut self) -> Vec<MidiEvent> {
+        std::mem::take(&mut self.sent_events)
+    }
 
-        for (_name, p, connected) in self.ports.iter_mut() {
-            *connected = port == p;
+    /// Sends a note-off for every note that is currently held down.
+    pub fn release_held_notes(&mut self) -> Result<(), MidiError> {
+        let mut result = Ok(());
+        for conn in self.connections.iter_mut() {
+            if let Err(err) = conn.release_held_notes(&mut self.sent_events) {
+                result = Err(err);
+            }
         }
+        result
     }
 
-    pub fn process_events(&mut self) {
-        if let Some(conn) = &mut self.connection {
-            for (channel, message) in self.event_queue.borrow_mut().drain(..) {
-                let mut buf = Vec::new();
-                let event = LiveEvent::Midi { channel, message };
-                event.write(&mut buf).unwrap();
-                conn.send(&buf).unwrap();
+    /// Sends All Notes Off and All Sound Off on every channel of every port,
+    /// silencing notes that graf may have lost track of as well.
+    pub fn panic(&mut self) -> Result<(), MidiError> {
+        let mut result = self.release_held_notes();
+
+        for conn in self.connections.iter_mut() {
+            for channel in 0..16u8 {
+                for controller in [ALL_NOTES_OFF, ALL_SOUND_OFF] {
+                    let message = MidiMessage::Controller {
+                        controller: controller.into(),
+                        value: 0.into(),
+                    };
+                    match conn.send(channel.into(), message) {
+                        Ok(()) => self.sent_events.push((
+                            MidiDestination::Port(conn.name.clone()),
+                            channel.into(),
+                            message,
+                        )),
+                        Err(err) => result = Err(err),
+                    }
+                }
             }
+            conn.held_notes.clear();
         }
+
+        result
     }
 
     pub fn get_event_sender(&self) -> MidiEventSender {
         MidiEventSender {
-            event_queue: self.event_queue.clone()
+            event_queue: self.event_queue.clone(),
+            output_names: self.output_names.clone(),
+        }
+    }
+}
+
+pub struct MidiInputConfig {
+    // None if the MIDI backend couldn't be initialized
+    pub midi_in: Option<MidiInput>,
+    pub ports: Vec<(String, MidiInputPort, bool)>,
+
+    connections: Vec<(String, MidiInputConnection<()>)>,
+
+    // ports the user asked to be connected to, which get reconnected
+    // automatically if they disappear and come back
+    wanted_ports: Vec<String>,
+    last_poll: Instant,
+
+    // filled from midir's input thread, drained on the main thread
+    received: Arc<Mutex<VecDeque<MidiInputEvent>>>,
+}
+
+impl MidiInputConfig {
+    /// Creates an unconnected config that will connect to the given ports
+    /// whenever they're available, just like `MidiConfig::new`.
+    pub fn new(wanted_ports: Vec<String>) -> Self {
+        MidiInputConfig {
+            midi_in: None,
+            ports: vec![],
+            connections: vec![],
+
+            wanted_ports,
+            last_poll: Instant::now(),
+
+            received: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub fn refresh_ports(&mut self) -> Result<(), MidiError> {
+        if self.midi_in.is_none() {
+            self.midi_in = Some(MidiInput::new("graf").map_err(MidiError::Init)?);
+        }
+        let Some(midi_in) = &self.midi_in else {
+            return Ok(());
+        };
+
+        self.ports.clear();
+        for port in midi_in.ports() {
+            if let Some(name) = midi_in
+                .port_name(&port)
+                .ok()
+                .map(|name| stable_port_name(&name))
+                .filter(|name| !is_own_virtual_port(name))
+            {
+                let connected = self.connections.iter().any(|(n, _)| *n == name);
+                self.ports.push((name, port, connected));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_connected(&self, port_name: &str) -> bool {
+        self.connections.iter().any(|(name, _)| name == port_name)
+    }
+
+    pub fn wanted_ports(&self) -> &[String] {
+        &self.wanted_ports
+    }
+
+    /// Ports that were connected by the user but are currently unavailable
+    pub fn missing_ports(&self) -> impl Iterator<Item = &String> {
+        self.wanted_ports
+            .iter()
+            .filter(|name| !self.is_connected(name))
+    }
+
+    pub fn connect_to_port(&mut self, port: &MidiInputPort) -> Result<(), MidiError> {
+        let name = match &self.midi_in {
+            Some(midi_in) => midi_in
+                .port_name(port)
+                .map(|name| stable_port_name(&name))
+                .map_err(MidiError::PortInfo)?,
+            None => return Err(MidiError::Init(InitError)),
+        };
+
+        if !self.wanted_ports.contains(&name) {
+            self.wanted_ports.push(name.clone());
+        }
+        if self.is_connected(&name) {
+            return Ok(());
+        }
+
+        let received = self.received.clone();
+        let midi_conn_in = MidiInput::new("graf-connection-input").map_err(MidiError::Init)?;
+        let connection = midi_conn_in
+            .connect(
+                port,
+                "graf-midi",
+                move |_timestamp, bytes, _| {
+                    if let Ok(LiveEvent::Midi { channel, message }) = LiveEvent::parse(bytes) {
+                        if let Ok(mut queue) = received.lock() {
+                            queue.push_back((channel, message));
+                        }
+                    }
+                },
+                (),
+            )
+            .map_err(|err| MidiError::Connect(name.clone(), err.kind()))?;
+        self.connections.push((name, connection));
+
+        self.connections_changed();
+        Ok(())
+    }
+
+    pub fn disconnect_port(&mut self, port_name: &str) {
+        self.wanted_ports.retain(|name| name != port_name);
+        self.connections.retain(|(name, _)| name != port_name);
+
+        self.connections_changed();
+    }
+
+    /// Drops connections to ports that have disappeared and reconnects to
+    /// wanted ports that have reappeared, like `MidiConfig::maintain_connections`.
+    pub fn maintain_connections(&mut self) -> Result<(), MidiError> {
+        // if the backend couldn't be opened, that's already been reported and
+        // it's only tried again when the user refreshes the ports
+        if self.midi_in.is_none() || self.last_poll.elapsed() < PORT_POLL_INTERVAL {
+            return Ok(());
+        }
+        self.last_poll = Instant::now();
+
+        self.refresh_ports()?;
+
+        let available: Vec<String> = self.ports.iter().map(|(name, _, _)| name.clone()).collect();
+        self.connections
+            .retain(|(name, _)| available.contains(name));
+        self.connections_changed();
+
+        self.connect_wanted_ports()
+    }
+
+    /// Connects to every wanted port that's available but not connected yet
+    pub fn connect_wanted_ports(&mut self) -> Result<(), MidiError> {
+        let mut result = Ok(());
+        let available: Vec<MidiInputPort> = self
+            .ports
+            .iter()
+            .filter(|(name, _, connected)| !connected && self.wanted_ports.contains(name))
+            .map(|(_, port, _)| port.clone())
+            .collect();
+        for port in available {
+            if let Err(err) = self.connect_to_port(&port) {
+                result = Err(err);
+            }
+        }
+
+        result
+    }
+
+    fn connections_changed(&mut self) {
+        for (name, _port, connected) in self.ports.iter_mut() {
+            *connected = self.connections.iter().any(|(n, _)| n == name);
+        }
+    }
+
+    /// Takes every event received since the last call
+    pub fn receive_events(&mut self) -> Vec<MidiInputEvent> {
+        match self.received.lock() {
+            Ok(mut queue) => queue.drain(..).collect(),
+            Err(_) => Vec::new(),
         }
     }
 }
@@ -0,0 +1,133 @@
+use egui::{Context, Id, Response};
+use midly::{
+    num::{u4, u7},
+    MidiMessage,
+};
+
+use crate::{dag::DeviceId, midi::MidiInputEvent};
+
+const PARAMETER_REQUEST_ID: &str = "midi_learn_parameter_request";
+
+/// Something a MIDI controller can be mapped to
+#[derive(Clone, Copy, PartialEq)]
+pub enum LearnTarget {
+    Parameter(DeviceId, &'static str),
+    Bpm,
+    PlayPause,
+    Reset,
+}
+
+impl LearnTarget {
+    /// Play/pause and reset are actions that fire when a button is pressed,
+    /// everything else follows the controller's value
+    pub fn is_button(&self) -> bool {
+        matches!(self, LearnTarget::PlayPause | LearnTarget::Reset)
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            LearnTarget::Parameter(_, name) => name,
+            LearnTarget::Bpm => "BPM",
+            LearnTarget::PlayPause => "Play/Pause",
+            LearnTarget::Reset => "Reset",
+        }
+    }
+}
+
+/// The control on a MIDI controller that a mapping listens to. Knobs and
+/// faders send CCs, while pads and buttons often send notes instead.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ControlSource {
+    Controller(u4, u7),
+    Note(u4, u7),
+}
+
+impl ControlSource {
+    /// Works out which control an incoming message came from and the value
+    /// (0 to 127) it was set to
+    pub fn from_event((channel, message): MidiInputEvent) -> Option<(Self, u8)> {
+        match message {
+            MidiMessage::Controller { controller, value } => Some((
+                ControlSource::Controller(channel, controller),
+                value.as_int(),
+            )),
+            MidiMessage::NoteOn { key, vel } => {
+                Some((ControlSource::Note(channel, key), vel.as_int()))
+            }
+            MidiMessage::NoteOff { key, .. } => Some((ControlSource::Note(channel, key), 0)),
+            _ => None,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ControlSource::Controller(channel, controller) => {
+                format!("CC {} (ch {})", controller, channel)
+            }
+            ControlSource::Note(channel, key) => format!("Note {} (ch {})", key, channel),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MidiMapping {
+    pub source: ControlSource,
+    pub target: LearnTarget,
+
+    // range that the controller's 0 to 127 gets scaled to
+    pub min: f32,
+    pub max: f32,
+
+    // last raw value received, so buttons only fire once per press
+    pub last_value: u8,
+}
+
+impl MidiMapping {
+    pub fn scale(&self, value: u8) -> f32 {
+        self.min + (self.max - self.min) * (value as f32 / 127.0)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum LearnAction {
+    Learn,
+    Forget,
+}
+
+/// Adds the MIDI learn context menu to a widget, returning what was chosen
+pub fn learn_menu(response: &Response) -> Option<LearnAction> {
+    let mut action = None;
+    response.context_menu(|ui| {
+        if ui.button("MIDI Learn").clicked() {
+            action = Some(LearnAction::Learn);
+            ui.close_menu();
+        }
+        if ui.button("Forget MIDI Mapping").clicked() {
+            action = Some(LearnAction::Forget);
+            ui.close_menu();
+        }
+    });
+    action
+}
+
+/// Adds the MIDI learn context menu to a widget in a device's inspector.
+///
+/// Inspectors don't know which device they belong to, so the request is
+/// stashed in egui's memory for the app to pick up with
+/// `take_parameter_request` once the inspector has been drawn.
+pub fn parameter_learn_menu(response: &Response, parameter: &'static str) {
+    if let Some(action) = learn_menu(response) {
+        response
+            .ctx
+            .data_mut(|data| data.insert_temp(Id::new(PARAMETER_REQUEST_ID), (action, parameter)));
+    }
+}
+
+pub fn take_parameter_request(ctx: &Context) -> Option<(LearnAction, &'static str)> {
+    let id = Id::new(PARAMETER_REQUEST_ID);
+    ctx.data_mut(|data| {
+        let request = data.get_temp(id);
+        data.remove::<(LearnAction, &'static str)>(id);
+        request
+    })
+}
@@ -0,0 +1,330 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use egui::{Align2, Color32, DragValue, FontId, Pos2, Rect, RichText, Sense, Stroke, Vec2};
+use midly::MidiMessage;
+
+use crate::{
+    drawing_utils::{color_to_color32, ColorPalette},
+    midi::{MidiDestination, MidiEvent, ALL_NOTES_OFF, ALL_SOUND_OFF},
+};
+
+// notes are kept for this many beats after they end, however much is shown
+const MAX_HISTORY_BEATS: f32 = 64.0;
+
+// oldest messages are dropped from the list past this many
+const MAX_MESSAGES: usize = 1000;
+
+const ROLL_HEIGHT: f32 = 200.0;
+const HEADER_HEIGHT: f32 = 16.0;
+const KEY_WIDTH: f32 = 32.0;
+
+// fewest keys the roll shows, so a couple of notes don't fill all of it
+const MIN_KEY_SPAN: u8 = 24;
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Name of a MIDI key, with the octave numbered like the note device does
+fn key_name(key: u8) -> String {
+    format!("{}{}", NOTE_NAMES[key as usize % 12], key / 12)
+}
+
+fn channel_color(channel: u8) -> Color32 {
+    egui::ecolor::Hsva::new(channel as f32 / 16.0, 0.7, 0.9, 1.0).into()
+}
+
+fn describe(message: &MidiMessage) -> String {
+    match *message {
+        MidiMessage::NoteOn { key, vel } if vel == 0 => {
+            format!("Note Off {}", key_name(key.as_int()))
+        }
+        MidiMessage::NoteOn { key, vel } => {
+            format!("Note On {} vel {}", key_name(key.as_int()), vel)
+        }
+        MidiMessage::NoteOff { key, vel } => {
+            format!("Note Off {} vel {}", key_name(key.as_int()), vel)
+        }
+        MidiMessage::Aftertouch { key, vel } => {
+            format!("Aftertouch {} {}", key_name(key.as_int()), vel)
+        }
+        MidiMessage::Controller { controller, value } => format!("CC {} = {}", controller, value),
+        MidiMessage::ProgramChange { program } => format!("Program {}", program),
+        MidiMessage::ChannelAftertouch { vel } => format!("Channel Aftertouch {}", vel),
+        MidiMessage::PitchBend { bend } => format!("Pitch Bend {}", bend.as_int()),
+    }
+}
+
+/// A note that was played, drawn as a bar on the piano roll
+struct RollNote {
+    channel: u8,
+    key: u8,
+    start: f32,
+
+    // None while the note is still held
+    end: Option<f32>,
+}
+
+/// A message as it was sent, for the message list
+struct LoggedMessage {
+    // time since the monitor was created
+    time: Duration,
+    beat: f32,
+    destination: MidiDestination,
+    channel: u8,
+    message: MidiMessage,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MonitorMode {
+    PianoRoll,
+    Messages,
+}
+
+/// Shows the MIDI the patch sends, either as notes on a scrolling piano roll
+/// or as a list of the raw messages
+pub struct MidiMonitor {
+    notes: VecDeque<RollNote>,
+    messages: VecDeque<LoggedMessage>,
+    started: Instant,
+
+    // beat of the latest recording
+    now: f32,
+
+    // how many beats the piano roll shows at once
+    window: f32,
+
+    mode: MonitorMode,
+}
+
+impl MidiMonitor {
+    pub fn new() -> Self {
+        MidiMonitor {
+            notes: VecDeque::new(),
+            messages: VecDeque::new(),
+            started: Instant::now(),
+            now: 0.0,
+            window: 8.0,
+            mode: MonitorMode::PianoRoll,
+        }
+    }
+
+    pub fn record(&mut self, beat: f32, events: Vec<MidiEvent>) {
+        // the beat clock only goes backwards when the session is reset
+        if beat < self.now {
+            self.notes.clear();
+        }
+        self.now = beat;
+
+        let time = self.started.elapsed();
+        for (destination, channel, message) in events {
+            let channel = channel.as_int();
+            match message {
+                MidiMessage::NoteOn { key, vel } if vel > 0 => self.notes.push_back(RollNote {
+                    channel,
+                    key: key.as_int(),
+                    start: beat,
+                    end: None,
+                }),
+                MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                    let key = key.as_int();
+                    let held = self
+                        .notes
+                        .iter_mut()
+                        .rev()
+                        .find(|n| n.channel == channel && n.key == key && n.end.is_none());
+                    if let Some(note) = held {
+                        note.end = Some(beat);
+                    }
+                }
+                MidiMessage::Controller { controller, .. }
+                    if [ALL_NOTES_OFF, ALL_SOUND_OFF].contains(&controller.as_int()) =>
+                {
+                    let held = self.notes.iter_mut().filter(|n| n.channel == channel);
+                    for note in held.filter(|n| n.end.is_none()) {
+                        note.end = Some(beat);
+                    }
+                }
+                _ => {}
+            }
+
+            self.messages.push_back(LoggedMessage {
+                time,
+                beat,
+                destination,
+                channel,
+                message,
+            });
+        }
+
+        while self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+
+        // held notes are kept however old they are, they might be stuck
+        let oldest = beat - MAX_HISTORY_BEATS;
+        self.notes
+            .retain(|n| !n.end.is_some_and(|end| end < oldest));
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, colors: &ColorPalette) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.mode, MonitorMode::PianoRoll, "Piano Roll");
+            ui.selectable_value(&mut self.mode, MonitorMode::Messages, "Messages");
+
+            ui.separator();
+
+            if self.mode == MonitorMode::PianoRoll {
+                ui.label("Beats");
+                ui.add(DragValue::new(&mut self.window).range(1.0..=MAX_HISTORY_BEATS));
+
+                ui.separator();
+            }
+
+            if ui.button("Clear").clicked() {
+                self.notes.clear();
+                self.messages.clear();
+            }
+
+            let held = self.notes.iter().filter(|n| n.end.is_none()).count();
+            ui.label(format!("{} held", held));
+        });
+
+        match self.mode {
+            MonitorMode::PianoRoll => self.piano_roll_ui(ui, colors),
+            MonitorMode::Messages => self.messages_ui(ui),
+        }
+    }
+
+    fn piano_roll_ui(&self, ui: &mut egui::Ui, colors: &ColorPalette) {
+        let size = Vec2::new(ui.available_width(), ROLL_HEIGHT);
+        let (response, painter) = ui.allocate_painter(size, Sense::hover());
+        let rect = response.rect;
+
+        let end = self.now;
+        let start = end - self.window;
+        let roll_left = rect.left() + KEY_WIDTH;
+        let x_at =
+            |beat: f32| roll_left + (beat - start) / self.window * (rect.right() - roll_left);
+
+        let visible = || {
+            self.notes
+                .iter()
+                .filter(move |n| n.start <= end && n.end.is_none_or(|e| e >= start))
+        };
+
+        // keys range over the visible notes, widened around their middle
+        let (lowest, highest) =
+            visible().fold((u8::MAX, 0), |(lo, hi), n| (lo.min(n.key), hi.max(n.key)));
+        let (lowest, highest) = if lowest > highest {
+            (48, 48 + MIN_KEY_SPAN)
+        } else if highest - lowest < MIN_KEY_SPAN {
+            let middle = (lowest as i32 + highest as i32) / 2;
+            let lowest = (middle - MIN_KEY_SPAN as i32 / 2).clamp(0, 127 - MIN_KEY_SPAN as i32);
+            (lowest as u8, lowest as u8 + MIN_KEY_SPAN)
+        } else {
+            (lowest, highest)
+        };
+
+        let key_height =
+            (rect.bottom() - rect.top() - HEADER_HEIGHT) / (highest - lowest + 1) as f32;
+        let y_at = |key: u8| rect.bottom() - (key - lowest + 1) as f32 * key_height;
+
+        let grid = color_to_color32(colors.bg_3);
+        let label = color_to_color32(colors.fg_3);
+
+        painter.rect_filled(rect, 0.0, color_to_color32(colors.bg_0));
+
+        // a line under every C, labeled in the gutter
+        for key in (lowest..=highest).filter(|k| k % 12 == 0) {
+            let y = y_at(key) + key_height;
+            painter.line_segment(
+                [Pos2::new(roll_left, y), Pos2::new(rect.right(), y)],
+                Stroke::new(1.0, grid),
+            );
+            painter.text(
+                Pos2::new(rect.left() + 2.0, y),
+                Align2::LEFT_BOTTOM,
+                key_name(key),
+                FontId::proportional(11.0),
+                label,
+            );
+        }
+
+        // a line for every beat, labeled at the start of every bar
+        for beat in start.ceil() as i32..=end.floor() as i32 {
+            let x = x_at(beat as f32);
+            painter.line_segment(
+                [Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+                Stroke::new(1.0, grid),
+            );
+            if beat % 4 == 0 {
+                painter.text(
+                    Pos2::new(x + 2.0, rect.top()),
+                    Align2::LEFT_TOP,
+                    beat.to_string(),
+                    FontId::proportional(11.0),
+                    label,
+                );
+            }
+        }
+
+        let mut hovered = None;
+        for note in visible() {
+            let left = x_at(note.start.max(start));
+            let right = x_at(note.end.unwrap_or(self.now).min(end)).max(left + 2.0);
+            let top = y_at(note.key);
+            let bar = Rect::from_min_max(
+                Pos2::new(left, top),
+                Pos2::new(right, top + key_height.max(2.0)),
+            );
+
+            painter.rect_filled(bar, 1.0, channel_color(note.channel));
+            if response.hover_pos().is_some_and(|pos| bar.contains(pos)) {
+                hovered = Some(note);
+            }
+        }
+
+        if let Some(note) = hovered {
+            response.on_hover_text(format!(
+                "{} on channel {}",
+                key_name(note.key),
+                note.channel
+            ));
+        }
+    }
+
+    fn messages_ui(&self, ui: &mut egui::Ui) {
+        if self.messages.is_empty() {
+            ui.label("Nothing has been sent yet");
+            return;
+        }
+
+        egui::ScrollArea::vertical()
+            .max_height(ROLL_HEIGHT)
+            .auto_shrink([false, true])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                egui::Grid::new("midi messages")
+                    .num_columns(5)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for logged in self.messages.iter() {
+                            ui.monospace(format!("{:.3}", logged.time.as_secs_f32()));
+                            ui.monospace(format!("{:.2}", logged.beat));
+                            ui.label(match &logged.destination {
+                                MidiDestination::AllPorts => "All ports",
+                                MidiDestination::Port(name) => name,
+                            });
+                            ui.label(
+                                RichText::new(format!("Channel {}", logged.channel))
+                                    .color(channel_color(logged.channel)),
+                            );
+                            ui.monospace(describe(&logged.message));
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+}
@@ -0,0 +1,93 @@
+use macroquad::{
+    math::{vec2, Rect, Vec2},
+    shapes::{draw_circle, draw_rectangle, draw_rectangle_lines},
+};
+
+use crate::app::DrawContext;
+
+const MINIMAP_WIDTH: f32 = 160.0;
+const MINIMAP_HEIGHT: f32 = 120.0;
+const MINIMAP_MARGIN: f32 = 10.0;
+
+// world space left around the devices, so ones at the edge aren't cut off
+const MINIMAP_PADDING: f32 = 32.0;
+
+/// Overview of the whole patch drawn in the bottom left corner of the canvas,
+/// with an outline of the part that's currently in view
+pub struct Minimap {
+    // where the minimap is on screen
+    pub rect: Rect,
+
+    // part of the world it shows, and how much it's shrunk to fit
+    bounds: Rect,
+    scale: f32,
+}
+
+impl Minimap {
+    /// `devices` is the bounding box of every device, in world coordinates
+    pub fn new(canvas: Rect, devices: Rect) -> Self {
+        let rect = Rect::new(
+            canvas.x + MINIMAP_MARGIN,
+            canvas.bottom() - MINIMAP_HEIGHT - MINIMAP_MARGIN,
+            MINIMAP_WIDTH,
+            MINIMAP_HEIGHT,
+        );
+
+        let shown = Rect::new(
+            devices.x - MINIMAP_PADDING,
+            devices.y - MINIMAP_PADDING,
+            devices.w + 2.0 * MINIMAP_PADDING,
+            devices.h + 2.0 * MINIMAP_PADDING,
+        );
+        let scale = f32::min(rect.w / shown.w, rect.h / shown.h);
+
+        // keep the aspect ratio, centering whatever doesn't fill the minimap
+        let size = vec2(rect.w, rect.h) / scale;
+        let center = shown.center();
+        let bounds = Rect::new(
+            center.x - size.x / 2.0,
+            center.y - size.y / 2.0,
+            size.x,
+            size.y,
+        );
+
+        Minimap {
+            rect,
+            bounds,
+            scale,
+        }
+    }
+
+    fn world_to_minimap(&self, world_coords: Vec2) -> Vec2 {
+        (world_coords - self.bounds.point()) * self.scale + self.rect.point()
+    }
+
+    pub fn minimap_to_world(&self, minimap_coords: Vec2) -> Vec2 {
+        (minimap_coords - self.rect.point()) / self.scale + self.bounds.point()
+    }
+
+    pub fn draw(&self, ctx: &DrawContext, devices: &[(Vec2, bool)], view: Rect) {
+        let Rect { x, y, w, h } = self.rect;
+        draw_rectangle(x, y, w, h, ctx.colors.bg_1);
+
+        for (pos, is_selected) in devices {
+            let pos = self.world_to_minimap(*pos);
+            let color = if *is_selected {
+                ctx.colors.fg_0
+            } else {
+                ctx.colors.fg_2
+            };
+            draw_circle(pos.x, pos.y, 2.0, color);
+        }
+
+        // only the part of the view that overlaps the minimap is outlined
+        let top_left = self.world_to_minimap(view.point()).max(self.rect.point());
+        let bottom_right = self
+            .world_to_minimap(view.point() + view.size())
+            .min(self.rect.point() + self.rect.size());
+        let size = (bottom_right - top_left).max(Vec2::ZERO);
+        draw_rectangle_lines(top_left.x, top_left.y, size.x, size.y, 1.0, ctx.colors.fg_1);
+
+        draw_rectangle_lines(x, y, w, h, 1.0, ctx.colors.bg_3);
+    }
+}
@@ -0,0 +1,751 @@
+use std::{collections::HashMap, fmt, ops::RangeInclusive, str::FromStr};
+
+use macroquad::math::Vec2;
+use midly::num::{u4, u7};
+
+use crate::{
+    comment::Comment,
+    dag::{Dag, DeviceId, Wire, WireType},
+    devices::{
+        clock::Clock,
+        counter::Counter,
+        debounce::Debounce,
+        delay::Delay,
+        edge::Edge,
+        gate::Gate,
+        latch::Latch,
+        note::Note,
+        quantizer::Quantizer,
+        ratchet::Ratchet,
+        sample_hold::SampleHold,
+        subpatch::{Inlet, Subpatch},
+        trigger::Trigger,
+        Device, SignalKind,
+    },
+    midi::MidiEventSender,
+    midi_learn::{ControlSource, LearnTarget, MidiMapping},
+    session::{Labels, Snippet},
+};
+
+/// First line of every saved patch, so patches can be recognized
+const PATCH_HEADER: &str = "# graf patch";
+
+/// Patches are saved as plain text, one device or wire per line:
+///
+/// ```text
+/// device 0 clock 0 0
+///   gate = 0.5
+/// end
+/// device 1 note 64 0
+///   label = Kick
+///   velocity = 100
+/// end
+/// wire 0 1 normal 0
+/// comment 0 48
+///   text = Four on the floor\nfor now
+/// end
+/// mapping cc 0 74 0 127 parameter 1 Velocity
+/// mapping note 9 36 0 1 play_pause
+/// ```
+///
+/// A device's settings go between its header line and `end`, and a subpatch's
+/// inner devices, wires and comments go there too. Positions are relative to
+/// the top-left of the saved devices and comments.
+///
+/// MIDI mappings give the control (`cc` or `note`, channel and number), the
+/// range it's scaled to and its target: a device parameter, `bpm`,
+/// `play_pause` or `reset`. They can only be in the top level of a patch.
+pub struct PatchWriter {
+    text: String,
+    indent: usize,
+}
+
+impl PatchWriter {
+    fn new() -> Self {
+        PatchWriter {
+            text: PATCH_HEADER.to_string() + "\n",
+            indent: 0,
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        self.text.push_str(&"  ".repeat(self.indent));
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    pub fn setting(&mut self, key: &str, value: impl fmt::Display) {
+        self.line(&format!("{} = {}", key, value));
+    }
+
+    pub fn fraction(&mut self, key: &str, (numerator, denominator): (u32, u32)) {
+        self.setting(key, format!("{}/{}", numerator, denominator));
+    }
+
+    /// Text typed in by the user, which may have line breaks
+    pub fn text(&mut self, key: &str, text: &str) {
+        self.setting(key, escape(text));
+    }
+
+    pub fn circuit<'a>(
+        &mut self,
+        devices: &HashMap<DeviceId, Box<dyn Device>>,
+        wires: impl Iterator<Item = &'a Wire>,
+        labels: &Labels,
+        comments: &[Comment],
+    ) {
+        let mut ids: Vec<&DeviceId> = devices.keys().collect();
+        ids.sort_by_key(|id| id.as_u32());
+
+        for id in ids {
+            let device = &devices[id];
+            let pos = device.get_position();
+            self.line(&format!(
+                "device {} {} {} {}",
+                id.as_u32(),
+                device.kind(),
+                pos.x,
+                pos.y
+            ));
+            self.indent += 1;
+            if let Some(label) = labels.get(id).filter(|l| !l.is_empty()) {
+                self.text("label", label);
+            }
+            device.save(self);
+            self.indent -= 1;
+            self.line("end");
+        }
+
+        for wire in wires {
+            let wire_type = match wire.wire_type {
+                WireType::Normal => "normal",
+                WireType::Negated => "negated",
+            };
+            let feedback = if wire.feedback { " feedback" } else { "" };
+            self.line(&format!(
+                "wire {} {} {} {}{}",
+                wire.from.as_u32(),
+                wire.to.as_u32(),
+                wire_type,
+                wire.port,
+                feedback
+            ));
+        }
+
+        for comment in comments {
+            self.line(&format!(
+                "comment {} {}",
+                comment.position.x, comment.position.y
+            ));
+            self.indent += 1;
+            self.text("text", &comment.text);
+            self.indent -= 1;
+            self.line("end");
+        }
+    }
+
+    pub fn mapping(&mut self, mapping: &MidiMapping) {
+        let source = match mapping.source {
+            ControlSource::Controller(channel, controller) => {
+                format!("cc {} {}", channel, controller)
+            }
+            ControlSource::Note(channel, key) => format!("note {} {}", channel, key),
+        };
+        let target = match mapping.target {
+            LearnTarget::Parameter(id, name) => format!("parameter {} {}", id.as_u32(), name),
+            LearnTarget::Bpm => "bpm".to_string(),
+            LearnTarget::PlayPause => "play_pause".to_string(),
+            LearnTarget::Reset => "reset".to_string(),
+        };
+        self.line(&format!(
+            "mapping {} {} {} {}",
+            source, mapping.min, mapping.max, target
+        ));
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+pub fn write_patch(snippet: &Snippet) -> String {
+    let (devices, wires, labels, comments, mappings) = snippet;
+    let mut writer = PatchWriter::new();
+    writer.circuit(devices, wires.iter(), labels, comments);
+    for mapping in mappings {
+        writer.mapping(mapping);
+    }
+    writer.text
+}
+
+pub fn is_patch(text: &str) -> bool {
+    text.trim_start().starts_with(PATCH_HEADER)
+}
+
+#[derive(Debug)]
+pub struct PatchError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+fn error<T>(line: usize, reason: impl Into<String>) -> Result<T, PatchError> {
+    Err(PatchError {
+        line,
+        reason: reason.into(),
+    })
+}
+
+/// A device as read from a patch, before it is built
+pub struct DeviceEntry {
+    line: usize,
+    id: u32,
+    kind: String,
+    position: Vec2,
+    settings: Vec<(String, String)>,
+    circuit: CircuitEntry,
+}
+
+impl DeviceEntry {
+    fn get<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.settings
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets `target` to the value saved under `key`, if there is one and it
+    /// parses
+    pub fn read<T: FromStr>(&self, key: &str, target: &mut T) {
+        if let Some(value) = self.get(key).next().and_then(|v| v.parse().ok()) {
+            *target = value;
+        }
+    }
+
+    /// Like `read`, but clamps the value into `range`, which should be the
+    /// range the inspector allows. Values that can't be compared at all, like
+    /// NaN, are left out.
+    pub fn read_clamped<T: FromStr + PartialOrd + Copy>(
+        &self,
+        key: &str,
+        target: &mut T,
+        range: RangeInclusive<T>,
+    ) {
+        let Some(value) = self.get(key).next().and_then(|v| v.parse().ok()) else {
+            return;
+        };
+        if value < *range.start() {
+            *target = *range.start();
+        } else if value > *range.end() {
+            *target = *range.end();
+        } else if range.contains(&value) {
+            *target = value;
+        }
+    }
+
+    pub fn read_string(&self, key: &str) -> Option<String> {
+        self.get(key).next().map(|v| v.to_string())
+    }
+
+    /// Reads back text saved with `PatchWriter::text`
+    pub fn read_text(&self, key: &str) -> Option<String> {
+        self.get(key).next().map(unescape)
+    }
+
+    /// Reads a fraction saved with `PatchWriter::fraction`, with both of its
+    /// parts clamped into `range`
+    pub fn read_fraction(&self, key: &str, target: &mut (u32, u32), range: RangeInclusive<u32>) {
+        let Some((n, d)) = self.get(key).next().and_then(|v| v.split_once('/')) else {
+            return;
+        };
+        if let (Ok(n), Ok(d)) = (n.trim().parse::<u32>(), d.trim().parse::<u32>()) {
+            let (min, max) = range.into_inner();
+            *target = (n.clamp(min, max), d.clamp(min, max));
+        }
+    }
+}
+
+struct WireEntry {
+    line: usize,
+    from: u32,
+    to: u32,
+    wire_type: WireType,
+    port: usize,
+    feedback: bool,
+}
+
+// parameter targets still refer to the device by its id in the patch, and
+// the parameter by name
+enum TargetEntry {
+    Parameter(u32, String),
+    Other(LearnTarget),
+}
+
+struct MappingEntry {
+    line: usize,
+    source: ControlSource,
+    target: TargetEntry,
+    min: f32,
+    max: f32,
+}
+
+#[derive(Default)]
+struct CircuitEntry {
+    devices: Vec<DeviceEntry>,
+    wires: Vec<WireEntry>,
+    comments: Vec<Comment>,
+    mappings: Vec<MappingEntry>,
+}
+
+type Lines<'a> = std::iter::Enumerate<std::str::Lines<'a>>;
+
+// parses devices and wires until an `end` line (if nested) or the end of the
+// text, along with any settings of the device the circuit is nested in
+fn parse_circuit(
+    lines: &mut Lines,
+    nested: bool,
+) -> Result<(CircuitEntry, Vec<(String, String)>), PatchError> {
+    let mut circuit = CircuitEntry::default();
+    let mut settings = Vec::new();
+
+    while let Some((i, line)) = lines.next() {
+        let line_no = i + 1;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[0] {
+            "end" if nested => return Ok((circuit, settings)),
+            "device" => {
+                let [_, id, kind, x, y] = words[..] else {
+                    return error(line_no, "expected `device <id> <kind> <x> <y>`");
+                };
+                let (Ok(id), Some(x), Some(y)) = (id.parse(), parse_number(x), parse_number(y))
+                else {
+                    return error(line_no, "bad device id or position");
+                };
+
+                let (inner, device_settings) = parse_circuit(lines, true)?;
+                circuit.devices.push(DeviceEntry {
+                    line: line_no,
+                    id,
+                    kind: kind.to_string(),
+                    position: Vec2::new(x, y),
+                    settings: device_settings,
+                    circuit: inner,
+                });
+            }
+            "wire" => {
+                let (from, to, wire_type, port, feedback) = match words[..] {
+                    [_, from, to, wire_type, port] => (from, to, wire_type, port, false),
+                    [_, from, to, wire_type, port, "feedback"] => (from, to, wire_type, port, true),
+                    _ => {
+                        return error(
+                            line_no,
+                            "expected `wire <from> <to> <normal|negated> <port> [feedback]`",
+                        )
+                    }
+                };
+                let wire_type = match wire_type {
+                    "normal" => WireType::Normal,
+                    "negated" => WireType::Negated,
+                    other => return error(line_no, format!("unknown wire type `{}`", other)),
+                };
+                let (Ok(from), Ok(to), Ok(port)) = (from.parse(), to.parse(), port.parse()) else {
+                    return error(line_no, "bad wire ends or port");
+                };
+
+                circuit.wires.push(WireEntry {
+                    line: line_no,
+                    from,
+                    to,
+                    wire_type,
+                    port,
+                    feedback,
+                });
+            }
+            "comment" => {
+                let [_, x, y] = words[..] else {
+                    return error(line_no, "expected `comment <x> <y>`");
+                };
+                let (Some(x), Some(y)) = (parse_number(x), parse_number(y)) else {
+                    return error(line_no, "bad comment position");
+                };
+
+                let (_, settings) = parse_circuit(lines, true)?;
+                let text = settings
+                    .iter()
+                    .find(|(key, _)| key == "text")
+                    .map(|(_, text)| unescape(text))
+                    .unwrap_or_default();
+                circuit.comments.push(Comment::new(Vec2::new(x, y), &text));
+            }
+            "mapping" if nested => {
+                return error(line_no, "mappings can only be in the top level");
+            }
+            "mapping" => circuit.mappings.push(parse_mapping(line_no, &words)?),
+            _ if nested => match line.split_once('=') {
+                Some((key, value)) => {
+                    settings.push((key.trim().to_string(), value.trim().to_string()))
+                }
+                None => return error(line_no, "expected `<setting> = <value>`"),
+            },
+            _ => return error(line_no, format!("unexpected `{}`", words[0])),
+        }
+    }
+
+    if nested {
+        return error(0, "missing `end`");
+    }
+    Ok((circuit, settings))
+}
+
+// positions and ranges, which have to be finite even though f32 parses `NaN`
+// and `inf` too
+fn parse_number(word: &str) -> Option<f32> {
+    word.parse().ok().filter(|x: &f32| x.is_finite())
+}
+
+fn parse_mapping(line_no: usize, words: &[&str]) -> Result<MappingEntry, PatchError> {
+    let [_, source, channel, number, min, max, target, ..] = words[..] else {
+        return error(
+            line_no,
+            "expected `mapping <cc|note> <channel> <number> <min> <max> <target>`",
+        );
+    };
+
+    let channel = channel.parse().ok().and_then(u4::try_from);
+    let number = number.parse().ok().and_then(u7::try_from);
+    let (Some(channel), Some(number)) = (channel, number) else {
+        return error(line_no, "bad mapping channel or number");
+    };
+    let source = match source {
+        "cc" => ControlSource::Controller(channel, number),
+        "note" => ControlSource::Note(channel, number),
+        other => return error(line_no, format!("unknown control `{}`", other)),
+    };
+
+    let (Some(min), Some(max)) = (parse_number(min), parse_number(max)) else {
+        return error(line_no, "bad mapping range");
+    };
+
+    let target = match (target, &words[7..]) {
+        ("bpm", []) => TargetEntry::Other(LearnTarget::Bpm),
+        ("play_pause", []) => TargetEntry::Other(LearnTarget::PlayPause),
+        ("reset", []) => TargetEntry::Other(LearnTarget::Reset),
+        ("parameter", [id, name @ ..]) if !name.is_empty() => match id.parse() {
+            Ok(id) => TargetEntry::Parameter(id, name.join(" ")),
+            Err(_) => return error(line_no, "bad mapping device id"),
+        },
+        _ => {
+            return error(
+                line_no,
+                "expected `bpm`, `play_pause`, `reset` or `parameter <id> <name>`",
+            )
+        }
+    };
+
+    Ok(MappingEntry {
+        line: line_no,
+        source,
+        target,
+        min,
+        max,
+    })
+}
+
+type BuiltCircuit = (
+    Dag,
+    HashMap<DeviceId, Box<dyn Device>>,
+    Labels,
+    HashMap<u32, DeviceId>,
+);
+
+// builds the devices of a circuit into a fresh Dag, returning it along with
+// the devices, their labels and a map from the ids in the patch to the new ids
+fn build_circuit(
+    entry: &CircuitEntry,
+    event_sender: &MidiEventSender,
+) -> Result<BuiltCircuit, PatchError> {
+    let mut circuit = Dag::new();
+    let mut devices = HashMap::new();
+    let mut labels = HashMap::new();
+    let mut id_map = HashMap::new();
+
+    for device_entry in entry.devices.iter() {
+        let device = build_device(device_entry, event_sender)?;
+        let id = circuit.add_device();
+        devices.insert(id, device);
+        if let Some(label) = device_entry.read_text("label") {
+            labels.insert(id, label);
+        }
+        id_map.insert(device_entry.id, id);
+    }
+
+    for wire in entry.wires.iter() {
+        let (Some(from), Some(to)) = (id_map.get(&wire.from), id_map.get(&wire.to)) else {
+            return error(wire.line, "wire between devices that don't exist");
+        };
+        if circuit
+            .add_wire(*from, *to, wire.wire_type, wire.port, wire.feedback)
+            .is_err()
+        {
+            return error(wire.line, "wire would create a loop");
+        }
+    }
+
+    Ok((circuit, devices, labels, id_map))
+}
+
+fn build_device(
+    entry: &DeviceEntry,
+    event_sender: &MidiEventSender,
+) -> Result<Box<dyn Device>, PatchError> {
+    let pos = entry.position;
+    let mut device: Box<dyn Device> = match entry.kind.as_str() {
+        "clock" => Box::new(Clock::new(pos)),
+        "counter" => Box::new(Counter::new(pos)),
+        "debounce" => Box::new(Debounce::new(pos)),
+        "delay" => Box::new(Delay::new(pos)),
+        "edge" => Box::new(Edge::new(pos)),
+        "gate" => Box::new(Gate::new(pos)),
+        "inlet" => Box::new(Inlet::new(pos, 0, SignalKind::Bool)),
+        "latch" => Box::new(Latch::new(pos)),
+        "note" => Box::new(Note::new(pos, event_sender.clone())),
+        "quantizer" => Box::new(Quantizer::new(pos)),
+        "ratchet" => Box::new(Ratchet::new(pos)),
+        "sample_hold" => Box::new(SampleHold::new(pos)),
+        "subpatch" => Box::new(build_subpatch(entry, event_sender)?),
+        "trigger" => Box::new(Trigger::new(pos)),
+        other => return error(entry.line, format!("unknown device `{}`", other)),
+    };
+    device.load(entry);
+    Ok(device)
+}
+
+fn build_subpatch(
+    entry: &DeviceEntry,
+    event_sender: &MidiEventSender,
+) -> Result<Subpatch, PatchError> {
+    let (circuit, devices, labels, id_map) = build_circuit(&entry.circuit, event_sender)?;
+
+    // inlets and outlets are saved as `<inner device id> <signal kind>`
+    let port = |value: &str| -> Result<(DeviceId, SignalKind), PatchError> {
+        let parsed = value.split_once(' ').and_then(|(id, kind)| {
+            let id = id_map.get(&id.parse().ok()?)?;
+            Some((*id, kind.parse().ok()?))
+        });
+        match parsed {
+            Some(port) => Ok(port),
+            None => error(entry.line, format!("bad subpatch port `{}`", value)),
+        }
+    };
+
+    let inlets = entry.get("inlet").map(port).collect::<Result<_, _>>()?;
+    let outlet = entry.get("outlet").next().map(port).transpose()?;
+
+    let mut subpatch = Subpatch::new(entry.position, devices, circuit, inlets, outlet);
+    subpatch.labels = labels;
+    subpatch.comments = entry.circuit.comments.clone();
+    Ok(subpatch)
+}
+
+/// Reads a patch written by `write_patch`, ready to be pasted into a session
+pub fn read_patch(text: &str, event_sender: &MidiEventSender) -> Result<Snippet, PatchError> {
+    let mut lines = text.lines().enumerate();
+    let (entry, _) = parse_circuit(&mut lines, false)?;
+
+    let (circuit, devices, labels, id_map) = build_circuit(&entry, event_sender)?;
+    let wires = circuit.wires().copied().collect();
+
+    let mut mappings = Vec::new();
+    for mapping in entry.mappings.iter() {
+        let target = match &mapping.target {
+            TargetEntry::Parameter(id, name) => {
+                let Some(id) = id_map.get(id) else {
+                    return error(mapping.line, "mapping of a device that doesn't exist");
+                };
+                // parameters are named by the device, so the name is looked up
+                // there rather than kept from the patch
+                let param = devices[id]
+                    .parameters()
+                    .into_iter()
+                    .find(|p| p.name == name);
+                match param {
+                    Some(param) => LearnTarget::Parameter(*id, param.name),
+                    None => {
+                        return error(mapping.line, format!("unknown parameter `{}`", name));
+                    }
+                }
+            }
+            TargetEntry::Other(target) => *target,
+        };
+        mappings.push(MidiMapping {
+            source: mapping.source,
+            target,
+            min: mapping.min,
+            max: mapping.max,
+            last_value: 0,
+        });
+    }
+
+    Ok((devices, wires, labels, entry.comments, mappings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::MidiConfig;
+
+    fn read(text: &str) -> Result<Snippet, PatchError> {
+        read_patch(text, &MidiConfig::new(Vec::new()).get_event_sender())
+    }
+
+    fn patch(body: &str) -> String {
+        format!("{}\n{}", PATCH_HEADER, body)
+    }
+
+    fn error_line(text: &str) -> usize {
+        match read(text) {
+            Ok(_) => panic!("patch should not have been read"),
+            Err(err) => err.line,
+        }
+    }
+
+    #[test]
+    fn reads_devices_wires_and_comments() {
+        let text = patch(
+            "device 4 clock 10 20\n  gate = 0.25\nend\n\
+             device 7 note 50 20\n  label = Kick\nend\n\
+             wire 4 7 negated 0\n\
+             comment 0 40\n  text = two\\nlines\nend\n",
+        );
+        let (devices, wires, labels, comments, mappings) = read(&text).unwrap();
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(wires.len(), 1);
+        assert!(wires[0].wire_type == WireType::Negated);
+        assert_eq!(labels[&wires[0].to], "Kick");
+        assert_eq!(devices[&wires[0].from].kind(), "clock");
+        assert_eq!(
+            devices[&wires[0].from].get_position(),
+            Vec2::new(10.0, 20.0)
+        );
+        assert_eq!(comments[0].text, "two\nlines");
+        assert!(mappings.is_empty());
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = patch(
+            "device 0 clock 0 0\n  label = Main \\\\ clock\n  bpm_sync = false\n  free_duration = 125\n  bpm_duration = 3/16\n  gate = 0.25\n  offset = 0.5\nend\n\
+             device 1 note 48 0\n  channel = 9\n  octave = 2\n  pitch = F#\n  velocity = 90\n  accent_velocity = 120\nend\n\
+             device 2 subpatch 96 0\n  inlet = 0 bool\n  outlet = 1 bool\n  device 0 inlet 0 0\n    label = Inner\n    index = 0\n    signal = bool\n  end\n  device 1 latch 48 0\n    on = true\n  end\n  wire 0 1 normal 0\nend\n\
+             wire 0 1 normal 0\n\
+             wire 0 2 normal 1\n\
+             wire 2 1 normal 1\n\
+             comment 0 48\n  text = Comment\nend\n\
+             mapping cc 0 74 0 127 parameter 1 Accent Velocity\n\
+             mapping note 9 36 0 1 play_pause\n",
+        );
+
+        let written = write_patch(&read(&text).unwrap());
+        assert_eq!(written, write_patch(&read(&written).unwrap()));
+        assert!(written.contains("label = Main \\\\ clock"));
+        assert!(written.contains("    label = Inner"));
+        assert!(written.contains("bpm_duration = 3/16"));
+        assert!(written.contains("mapping cc 0 74 0 127 parameter 1 Accent Velocity"));
+        assert!(written.contains("mapping note 9 36 0 1 play_pause"));
+    }
+
+    #[test]
+    fn clamps_settings_to_inspector_ranges() {
+        let text = patch(
+            "device 0 ratchet 0 0\n  count = 4000000000\n  gate = 7\n  curve = NaN\n  free_duration = -5\n  bpm_duration = 0/100000\nend\n\
+             device 1 clock 0 0\n  gate = -1\n  offset = inf\nend\n",
+        );
+        let written = write_patch(&read(&text).unwrap());
+
+        assert!(written.contains("count = 64"));
+        assert!(written.contains("gate = 1\n"));
+        assert!(written.contains("curve = 0\n"));
+        assert!(written.contains("free_duration = 1\n"));
+        assert!(written.contains("bpm_duration = 1/256"));
+        assert!(written.contains("gate = 0\n"));
+        assert!(written.contains("offset = 1\n"));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(error_line(&patch("device 0 clock 0\nend\n")), 2);
+        assert_eq!(error_line(&patch("device 0 clock NaN 0\nend\n")), 2);
+        assert_eq!(error_line(&patch("device 0 kazoo 0 0\nend\n")), 2);
+        assert_eq!(
+            error_line(&patch("device 0 clock 0 0\n  gate 0.5\nend\n")),
+            3
+        );
+        assert_eq!(error_line(&patch("device 0 clock 0 0\n")), 0);
+        assert_eq!(error_line(&patch("wire 0 1 normal 0\n")), 2);
+        assert_eq!(error_line(&patch("bogus\n")), 2);
+        assert_eq!(
+            error_line(&patch(
+                "device 0 latch 0 0\nend\nwire 0 0 normal 0 feedback\n"
+            )),
+            4
+        );
+        assert_eq!(
+            error_line(&patch("device 0 latch 0 0\nend\nwire 0 0 twisted 0\n")),
+            4
+        );
+        assert_eq!(error_line(&patch("comment 0\n")), 2);
+    }
+
+    #[test]
+    fn rejects_bad_mappings() {
+        let device = "device 0 clock 0 0\nend\n";
+        for (mapping, line) in [
+            ("mapping cc 16 1 0 1 bpm", 4),
+            ("mapping cc 0 128 0 1 bpm", 4),
+            ("mapping pedal 0 1 0 1 bpm", 4),
+            ("mapping cc 0 1 0 NaN bpm", 4),
+            ("mapping cc 0 1 0 1 tempo", 4),
+            ("mapping cc 0 1 0 1 parameter 5 Gate", 4),
+            ("mapping cc 0 1 0 1 parameter 0 Volume", 4),
+        ] {
+            assert_eq!(
+                error_line(&patch(&format!("{}{}\n", device, mapping))),
+                line
+            );
+        }
+
+        let nested = "device 0 subpatch 0 0\n  mapping cc 0 1 0 1 bpm\nend\n";
+        assert_eq!(error_line(&patch(nested)), 3);
+    }
+}
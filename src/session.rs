@@ -8,17 +8,28 @@ use macroquad::math::{Rect, Vec2};
 
 use crate::{
     app::DrawContext,
+    comment::Comment,
     dag::{self, Dag, DeviceId, Wire, WireType},
-    devices::{Arity, Device},
-    drawing_utils::draw_wire_between_devices,
+    devices::{
+        subpatch::{Inlet, Subpatch},
+        Arity, Device, Signal, SignalKind,
+    },
+    drawing_utils::{draw_device_label, draw_port_label, draw_wire_between_devices},
+    midi::Transpose,
+    midi_learn::{ControlSource, LearnTarget, MidiMapping},
 };
 
 const SNAP_GRID_SIZE: f32 = 16.0;
 
+// default range for BPM mapped to a MIDI controller, the full range of the
+// BPM field would be way too coarse to be usable on a knob
+const MAPPED_BPM_RANGE: (f32, f32) = (60.0, 180.0);
+
 pub struct UpdateContext {
     pub beat_clock: f32,
     pub free_clock: Duration,
     pub bpm: u32,
+    pub transpose: Transpose,
 
     pub this_update: Instant,
     pub last_update: Instant,
@@ -32,6 +43,7 @@ impl UpdateContext {
             beat_clock: 0.0,
             free_clock: Duration::ZERO,
             bpm: 120,
+            transpose: Transpose::new(),
 
             this_update: Instant::now(),
             last_update: Instant::now(),
@@ -41,14 +53,50 @@ impl UpdateContext {
     }
 }
 
+/// Names the user has given devices, drawn under them
+pub type Labels = HashMap<DeviceId, String>;
+
+/// A set of devices, the wires between them, their labels, comments and the
+/// MIDI mappings of the devices, with positions relative to their top-left
+/// corner
+pub type Snippet = (
+    HashMap<DeviceId, Box<dyn Device>>,
+    Vec<Wire>,
+    Labels,
+    Vec<Comment>,
+    Vec<MidiMapping>,
+);
+
+// the patch a subpatch being edited was opened from, set aside until it is
+// closed again
+struct ParentPatch {
+    subpatch: DeviceId,
+    devices: HashMap<DeviceId, Box<dyn Device>>,
+    circuit: Dag,
+    labels: Labels,
+    comments: Vec<Comment>,
+    selected: Vec<DeviceId>,
+}
+
 pub struct Session {
     pub devices: HashMap<DeviceId, Box<dyn Device>>,
     pub circuit: Dag,
+    pub labels: Labels,
+    pub comments: Vec<Comment>,
 
     pub selected: Vec<DeviceId>,
-    pub clipboard: (HashMap<DeviceId, Box<dyn Device>>, Vec<Wire>),
+    pub clipboard: Snippet,
+
+    pub midi_mappings: Vec<MidiMapping>,
 
     pub update_ctx: UpdateContext,
+
+    // device outputs from the last update, read by feedback wires
+    prev_outputs: HashMap<DeviceId, Signal>,
+
+    // while a subpatch is open, `devices` and `circuit` hold its contents and
+    // the patches above it are kept here, outermost first
+    parents: Vec<ParentPatch>,
 }
 
 impl Session {
@@ -56,11 +104,25 @@ impl Session {
         Session {
             devices: HashMap::new(),
             circuit: Dag::new(),
+            labels: HashMap::new(),
+            comments: Vec::new(),
 
             selected: Vec::new(),
-            clipboard: (HashMap::new(), Vec::new()),
+            clipboard: (
+                HashMap::new(),
+                Vec::new(),
+                HashMap::new(),
+                Vec::new(),
+                Vec::new(),
+            ),
+
+            midi_mappings: Vec::new(),
 
             update_ctx: UpdateContext::new(),
+
+            prev_outputs: HashMap::new(),
+
+            parents: Vec::new(),
         }
     }
 
@@ -71,15 +133,40 @@ impl Session {
         id
     }
 
-    pub fn connect_devices(&mut self, from: DeviceId, to: DeviceId, wire_type: WireType) {
+    pub fn connect_devices(
+        &mut self,
+        from: DeviceId,
+        to: DeviceId,
+        wire_type: WireType,
+        port: usize,
+        feedback: bool,
+    ) {
         // just silently ignore any errors for now
-        if let Err(dag::IllegalWireError) = self.circuit.add_wire(from, to, wire_type) {
+        if let Err(dag::IllegalWireError) =
+            self.circuit.add_wire(from, to, wire_type, port, feedback)
+        {
             print!("Got IllegalEdgeError when trying to connected devices!!!");
         }
     }
 
-    pub fn disconnect_devices(&mut self, from: DeviceId, to: DeviceId) {
-        self.circuit.remove_wire(from, to)
+    pub fn disconnect_devices(&mut self, from: DeviceId, to: DeviceId, port: usize) {
+        self.circuit.remove_wire(from, to, port)
+    }
+
+    /// Adds a comment and selects only it
+    pub fn add_comment(&mut self, comment: Comment) {
+        self.clear_selection();
+        self.comments.push(Comment {
+            selected: true,
+            ..comment
+        });
+    }
+
+    // the last comment is drawn on top, so it's the one found first
+    pub fn get_comment_at(&self, position: Vec2) -> Option<usize> {
+        self.comments
+            .iter()
+            .rposition(|c| c.rect().contains(position))
     }
 
     pub fn get_device_at(&self, position: Vec2) -> Option<DeviceId> {
@@ -91,7 +178,9 @@ impl Session {
         None
     }
 
-    pub fn get_wire_at(&self, position: Vec2) -> Option<Wire> {
+    // wires are clickable within a fixed distance on screen, so it's divided
+    // by the scale things are drawn at
+    pub fn get_wire_at(&self, position: Vec2, scale: f32) -> Option<Wire> {
         const WIRE_CLICKABLE_DISTANCE: f32 = 5.0;
 
         for edge in self.circuit.wires() {
@@ -101,13 +190,13 @@ impl Session {
             let len2 = u.distance_squared(v);
 
             if len2 == 0.0 {
-                return None;
+                continue;
             }
 
             let t = ((position - u).dot(v - u) / len2).clamp(0.0, 1.0);
             let point_on_line = u + t * (v - u);
 
-            if position.distance(point_on_line) < WIRE_CLICKABLE_DISTANCE {
+            if position.distance(point_on_line) < WIRE_CLICKABLE_DISTANCE / scale {
                 return Some(*edge);
             }
         }
@@ -115,21 +204,87 @@ impl Session {
         None
     }
 
-    pub fn can_connect(&self, from: DeviceId, to: DeviceId) -> bool {
-        let to_dev = self.devices.get(&to).unwrap();
-        if to_dev.input_arity() == Arity::Nullary {
-            return false;
-        } else if to_dev.input_arity() == Arity::Unary && self.circuit.incoming(to).count() > 0 {
-            return false;
+    /// Would a wire between the two devices close a loop? Such a wire has to
+    /// be a feedback wire.
+    pub fn closes_loop(&self, from: DeviceId, to: DeviceId) -> bool {
+        self.circuit.is_reachable(to, from)
+    }
+
+    pub fn can_connect(&self, from: DeviceId, to: DeviceId, feedback: bool) -> bool {
+        self.free_input_port(from, to, feedback).is_some()
+    }
+
+    /// Picks the port a new wire between the two devices should be plugged
+    /// into: the main input if it has room, otherwise the first free aux input.
+    pub fn free_input_port(&self, from: DeviceId, to: DeviceId, feedback: bool) -> Option<usize> {
+        self.free_input_ports(from, to, feedback).first().copied()
+    }
+
+    /// Every port a new wire between the two devices could be plugged into,
+    /// the main input first. Only inputs taking the kind of signal `from`
+    /// outputs are considered, and loops can only be closed by feedback wires.
+    /// A device can't be wired into itself, even if it allows feedback.
+    pub fn free_input_ports(&self, from: DeviceId, to: DeviceId, feedback: bool) -> Vec<usize> {
+        if from == to {
+            return Vec::new();
+        }
+
+        let from_dev = self.devices.get(&from).unwrap();
+        if self.closes_loop(from, to) && !feedback && !from_dev.allows_feedback() {
+            return Vec::new();
         }
 
-        !self.circuit.is_reachable(to, from)
+        let kind = from_dev.output_kind();
+        let to_dev = self.devices.get(&to).unwrap();
+        let port_taken = |port: usize| self.circuit.incoming(to).any(|w| w.port == port);
+
+        let main_free = match to_dev.input_arity() {
+            Arity::Nullary => false,
+            Arity::Unary => !port_taken(0),
+            Arity::NAry => true,
+        };
+        let main = (main_free && to_dev.input_kind() == kind).then_some(0);
+
+        let aux = to_dev
+            .aux_inputs()
+            .into_iter()
+            .zip(1..)
+            .filter(|(input, port)| input.kind == kind && !port_taken(*port))
+            .map(|(_, port)| port);
+
+        main.into_iter().chain(aux).collect()
+    }
+
+    /// Name of one of a device's input ports, as it's offered when choosing
+    /// where a wire goes
+    pub fn input_port_name(&self, id: DeviceId, port: usize) -> String {
+        let aux = port.checked_sub(1).and_then(|i| {
+            let device = self.devices.get(&id)?;
+            device.aux_inputs().into_iter().nth(i)
+        });
+        match aux {
+            Some(input) => input.name,
+            None => "Input".to_string(),
+        }
     }
 
     pub fn device_position(&self, id: DeviceId) -> Option<Vec2> {
         self.devices.get(&id).map(|d| d.get_position())
     }
 
+    /// Smallest rectangle containing the positions of the given devices
+    pub fn device_bounds(&self, ids: impl IntoIterator<Item = DeviceId>) -> Option<Rect> {
+        let positions: Vec<Vec2> = ids
+            .into_iter()
+            .filter_map(|id| self.device_position(id))
+            .collect();
+
+        let top_left = positions.iter().copied().reduce(Vec2::min)?;
+        let bottom_right = positions.iter().copied().reduce(Vec2::max)?;
+        let size = bottom_right - top_left;
+        Some(Rect::new(top_left.x, top_left.y, size.x, size.y))
+    }
+
     pub fn move_device(&mut self, device_id: DeviceId, delta: Vec2) {
         if let Some(device) = self.devices.get_mut(&device_id) {
             let new_pos = device.get_position() + delta;
@@ -152,6 +307,44 @@ impl Session {
 
     pub fn clear_selection(&mut self) {
         self.selected.clear();
+        for comment in self.comments.iter_mut() {
+            comment.selected = false;
+        }
+    }
+
+    pub fn select_comment(&mut self, index: usize) {
+        if let Some(comment) = self.comments.get_mut(index) {
+            comment.selected = true;
+        }
+    }
+
+    pub fn toggle_comment_selection(&mut self, index: usize) {
+        if let Some(comment) = self.comments.get_mut(index) {
+            comment.selected = !comment.selected;
+        }
+    }
+
+    /// Indices of the selected comments
+    pub fn selected_comments(&self) -> Vec<usize> {
+        (0..self.comments.len())
+            .filter(|i| self.comments[*i].selected)
+            .collect()
+    }
+
+    pub fn has_selection(&self) -> bool {
+        !self.selected.is_empty() || self.comments.iter().any(|c| c.selected)
+    }
+
+    /// The selected comment, if it's the only thing selected
+    pub fn selected_comment_mut(&mut self) -> Option<&mut Comment> {
+        if !self.selected.is_empty() {
+            return None;
+        }
+        let mut selected = self.comments.iter_mut().filter(|c| c.selected);
+        match (selected.next(), selected.next()) {
+            (Some(comment), None) => Some(comment),
+            _ => None,
+        }
     }
 
     pub fn select_device(&mut self, device_id: DeviceId) {
@@ -160,10 +353,37 @@ impl Session {
         }
     }
 
+    pub fn toggle_device_selection(&mut self, device_id: DeviceId) {
+        match self.selected.iter().position(|id| *id == device_id) {
+            Some(i) => {
+                self.selected.remove(i);
+            }
+            None => self.selected.push(device_id),
+        }
+    }
+
+    pub fn select_all_devices(&mut self) {
+        self.selected = self.devices.keys().copied().collect();
+        for comment in self.comments.iter_mut() {
+            comment.selected = true;
+        }
+    }
+
     pub fn select_devices_in_rect(&mut self, rect: Rect) {
-        for (id, device) in self.devices.iter() {
-            if rect.contains(device.get_position()) {
-                self.selected.push(*id);
+        let inside: Vec<DeviceId> = self
+            .devices
+            .iter()
+            .filter(|(_, device)| rect.contains(device.get_position()))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in inside {
+            self.select_device(id);
+        }
+
+        for comment in self.comments.iter_mut() {
+            if rect.contains(comment.rect().center()) {
+                comment.selected = true;
             }
         }
     }
@@ -173,6 +393,10 @@ impl Session {
             let pos = self.device_position(*dev_id).unwrap() + delta;
             self.devices.get_mut(dev_id).map(|d| d.set_position(pos));
         }
+
+        for comment in self.comments.iter_mut().filter(|c| c.selected) {
+            comment.position += delta;
+        }
     }
 
     pub fn snap_selected_to_grid(&mut self) {
@@ -180,37 +404,398 @@ impl Session {
         for dev_id in selected {
             self.snap_device_to_grid(dev_id);
         }
+
+        for comment in self.comments.iter_mut().filter(|c| c.selected) {
+            comment.position = (comment.position / SNAP_GRID_SIZE).round() * SNAP_GRID_SIZE;
+        }
     }
 
     pub fn delete_selected_devices(&mut self) {
         for dev_id in &self.selected {
             self.circuit.remove_device(*dev_id);
             self.devices.remove(&dev_id);
+            self.labels.remove(dev_id);
         }
+        self.comments.retain(|c| !c.selected);
 
+        let outlet_deleted = self
+            .edited_subpatch()
+            .and_then(|s| s.outlet())
+            .is_some_and(|(id, _)| self.selected.contains(&id));
+        if outlet_deleted {
+            self.set_edited_outlet(None);
+        }
+
+        self.forget_orphaned_mappings();
         self.clear_selection();
     }
 
+    // MIDI mappings can only target devices in the top level patch
+    fn root_devices(&mut self) -> &mut HashMap<DeviceId, Box<dyn Device>> {
+        match self.parents.first_mut() {
+            Some(root) => &mut root.devices,
+            None => &mut self.devices,
+        }
+    }
+
+    fn forget_orphaned_mappings(&mut self) {
+        let mut mappings = std::mem::take(&mut self.midi_mappings);
+        let devices = self.root_devices();
+        mappings.retain(|m| match m.target {
+            LearnTarget::Parameter(id, _) => devices.contains_key(&id),
+            _ => true,
+        });
+        self.midi_mappings = mappings;
+    }
+
+    pub fn is_editing_subpatch(&self) -> bool {
+        !self.parents.is_empty()
+    }
+
+    /// Swaps the contents of a subpatch in for editing. Returns false if the
+    /// device isn't a subpatch.
+    pub fn open_subpatch(&mut self, id: DeviceId) -> bool {
+        let Some(subpatch) = self.devices.get_mut(&id).and_then(|d| d.as_subpatch_mut()) else {
+            return false;
+        };
+
+        let devices = std::mem::take(&mut subpatch.devices);
+        let circuit = std::mem::replace(&mut subpatch.circuit, Dag::new());
+        let labels = std::mem::take(&mut subpatch.labels);
+        let comments = std::mem::take(&mut subpatch.comments);
+        self.parents.push(ParentPatch {
+            subpatch: id,
+            devices: std::mem::replace(&mut self.devices, devices),
+            circuit: std::mem::replace(&mut self.circuit, circuit),
+            labels: std::mem::replace(&mut self.labels, labels),
+            comments: std::mem::replace(&mut self.comments, comments),
+            selected: std::mem::take(&mut self.selected),
+        });
+        true
+    }
+
+    /// Puts the contents of the subpatch being edited back into it and goes
+    /// back to editing the patch it was opened from.
+    pub fn close_subpatch(&mut self) {
+        let Some(parent) = self.parents.pop() else {
+            return;
+        };
+
+        let devices = std::mem::replace(&mut self.devices, parent.devices);
+        let circuit = std::mem::replace(&mut self.circuit, parent.circuit);
+        let labels = std::mem::replace(&mut self.labels, parent.labels);
+        let comments = std::mem::replace(&mut self.comments, parent.comments);
+        self.selected = parent.selected;
+
+        if let Some(subpatch) = self
+            .devices
+            .get_mut(&parent.subpatch)
+            .and_then(|d| d.as_subpatch_mut())
+        {
+            subpatch.devices = devices;
+            subpatch.circuit = circuit;
+            subpatch.labels = labels;
+            subpatch.comments = comments;
+        }
+    }
+
+    // the subpatch being edited, as kept in the patch it was opened from
+    fn edited_subpatch(&self) -> Option<&Subpatch> {
+        let parent = self.parents.last()?;
+        parent.devices.get(&parent.subpatch)?.as_subpatch()
+    }
+
+    /// Inputs of the subpatch being edited whose Inlet device has been deleted
+    pub fn missing_inlets(&self) -> Vec<usize> {
+        let Some(subpatch) = self.edited_subpatch() else {
+            return Vec::new();
+        };
+        (0..subpatch.inlets().len())
+            .filter(|i| !self.devices.contains_key(&subpatch.inlets()[*i].0))
+            .collect()
+    }
+
+    /// Puts back the Inlet device of an input of the subpatch being edited
+    pub fn restore_inlet(&mut self, index: usize, position: Vec2) {
+        let Some(kind) = self
+            .edited_subpatch()
+            .and_then(|s| s.inlets().get(index))
+            .map(|(_, kind)| *kind)
+        else {
+            return;
+        };
+        self.add_inlet(index, kind, position);
+    }
+
+    /// Adds an input to the subpatch being edited, along with its Inlet device
+    pub fn add_subpatch_input(&mut self, kind: SignalKind, position: Vec2) {
+        if let Some(index) = self.edited_subpatch().map(|s| s.inlets().len()) {
+            self.add_inlet(index, kind, position);
+        }
+    }
+
+    fn add_inlet(&mut self, index: usize, kind: SignalKind, position: Vec2) {
+        let id = self.add_device(Box::new(Inlet::new(position, index, kind)));
+        if let Some(parent) = self.parents.last_mut() {
+            if let Some(subpatch) = parent
+                .devices
+                .get_mut(&parent.subpatch)
+                .and_then(|d| d.as_subpatch_mut())
+            {
+                subpatch.set_inlet(index, id, kind);
+            }
+        }
+    }
+
+    /// Can the selected device become the output of the subpatch being edited?
+    pub fn can_set_outlet(&self) -> bool {
+        let [id] = self.selected[..] else {
+            return false;
+        };
+        let Some(subpatch) = self.edited_subpatch() else {
+            return false;
+        };
+        self.devices.get(&id).is_some_and(|d| d.has_output())
+            && subpatch.outlet().map(|(outlet, _)| outlet) != Some(id)
+    }
+
+    pub fn set_selected_as_outlet(&mut self) {
+        if !self.can_set_outlet() {
+            return;
+        }
+        let id = self.selected[0];
+        let kind = self.devices[&id].output_kind();
+        self.set_edited_outlet(Some((id, kind)));
+    }
+
+    // changes the output of the subpatch being edited, unplugging the wires out
+    // of it in the parent patch if it no longer has an output of their kind
+    fn set_edited_outlet(&mut self, outlet: Option<(DeviceId, SignalKind)>) {
+        let Some(parent) = self.parents.last_mut() else {
+            return;
+        };
+        let Some(subpatch) = parent
+            .devices
+            .get_mut(&parent.subpatch)
+            .and_then(|d| d.as_subpatch_mut())
+        else {
+            return;
+        };
+
+        let old_kind = subpatch.outlet().map(|(_, kind)| kind);
+        subpatch.set_outlet(outlet);
+
+        if old_kind != outlet.map(|(_, kind)| kind) {
+            let targets: Vec<(DeviceId, usize)> = parent
+                .circuit
+                .wires()
+                .filter(|w| w.from == parent.subpatch)
+                .map(|w| (w.to, w.port))
+                .collect();
+            for (to, port) in targets {
+                parent.circuit.remove_wire(parent.subpatch, to, port);
+            }
+        }
+    }
+
+    /// Are any of the selected devices the target of a MIDI mapping? Mappings
+    /// can't reach into subpatches, so those can't be grouped.
+    pub fn selection_is_mapped(&self) -> bool {
+        !self.is_editing_subpatch()
+            && self.midi_mappings.iter().any(|m| match m.target {
+                LearnTarget::Parameter(id, _) => self.selected.contains(&id),
+                _ => false,
+            })
+    }
+
+    /// The selection can be grouped if at most one of the selected devices is
+    /// wired to something outside it (a subpatch only has one output), and
+    /// grouping it wouldn't create a loop through the devices around it.
+    /// Devices with MIDI mappings can't be grouped either.
+    pub fn can_group_selected(&self) -> bool {
+        if self.selected.is_empty() || self.selection_is_mapped() {
+            return false;
+        }
+
+        let inside = |id: &DeviceId| self.selected.contains(id);
+        let outgoing: Vec<&Wire> = self
+            .circuit
+            .wires()
+            .filter(|w| inside(&w.from) && !inside(&w.to))
+            .collect();
+        let incoming: Vec<&Wire> = self
+            .circuit
+            .wires()
+            .filter(|w| !inside(&w.from) && inside(&w.to))
+            .collect();
+
+        if outgoing.iter().any(|w| w.from != outgoing[0].from) {
+            return false;
+        }
+
+        !outgoing.iter().filter(|w| !w.feedback).any(|out| {
+            incoming
+                .iter()
+                .filter(|w| !w.feedback)
+                .any(|inc| self.circuit.is_reachable(out.to, inc.from))
+        })
+    }
+
+    /// Moves the selected devices into a new subpatch. Every wire coming into
+    /// the selection gets its own input on the subpatch, and wires going out
+    /// of it come out of the subpatch instead.
+    pub fn group_selected_into_subpatch(&mut self) {
+        if !self.can_group_selected() {
+            return;
+        }
+
+        let selected = std::mem::take(&mut self.selected);
+        let inside = |id: &DeviceId| selected.contains(id);
+        let wires: Vec<Wire> = self.circuit.wires().copied().collect();
+
+        let mut circuit = Dag::new();
+        let mut devices = HashMap::new();
+        let mut labels = HashMap::new();
+        let mut id_map = HashMap::new();
+        let mut center = Vec2::ZERO;
+        for id in selected.iter() {
+            let device = self.devices.remove(id).unwrap();
+            center += device.get_position();
+
+            let new_id = circuit.add_device();
+            devices.insert(new_id, device);
+            if let Some(label) = self.labels.remove(id) {
+                labels.insert(new_id, label);
+            }
+            id_map.insert(*id, new_id);
+        }
+        center /= selected.len() as f32;
+
+        for wire in wires.iter().filter(|w| inside(&w.from) && inside(&w.to)) {
+            let _ = circuit.add_wire(
+                id_map[&wire.from],
+                id_map[&wire.to],
+                wire.wire_type,
+                wire.port,
+                wire.feedback,
+            );
+        }
+
+        let incoming: Vec<Wire> = wires
+            .iter()
+            .filter(|w| !inside(&w.from) && inside(&w.to))
+            .copied()
+            .collect();
+        let outgoing: Vec<Wire> = wires
+            .iter()
+            .filter(|w| inside(&w.from) && !inside(&w.to))
+            .copied()
+            .collect();
+
+        // each device wired into the selection is replaced by an inlet inside
+        // the subpatch, placed where that device was
+        let mut inlets = Vec::new();
+        let mut sources: Vec<(DeviceId, bool)> = Vec::new();
+        for wire in incoming.iter() {
+            let index = match sources.iter().position(|(id, _)| *id == wire.from) {
+                Some(index) => index,
+                None => {
+                    let source = self.devices.get(&wire.from).unwrap();
+                    let kind = source.output_kind();
+                    let inlet = Inlet::new(source.get_position(), inlets.len(), kind);
+
+                    let inlet_id = circuit.add_device();
+                    devices.insert(inlet_id, Box::new(inlet) as Box<dyn Device>);
+                    inlets.push((inlet_id, kind));
+                    sources.push((wire.from, wire.feedback));
+                    inlets.len() - 1
+                }
+            };
+
+            let inlet_id = inlets[index].0;
+            let _ = circuit.add_wire(inlet_id, id_map[&wire.to], wire.wire_type, wire.port, false);
+        }
+
+        let outlet = outgoing.first().map(|wire| {
+            let id = id_map[&wire.from];
+            (id, devices[&id].output_kind())
+        });
+
+        for id in selected.iter() {
+            self.circuit.remove_device(*id);
+        }
+
+        let mut subpatch = Subpatch::new(center, devices, circuit, inlets, outlet);
+        subpatch.labels = labels;
+        let subpatch_id = self.add_device(Box::new(subpatch));
+        self.labels.insert(subpatch_id, "Subpatch".to_string());
+
+        for (i, (source, feedback)) in sources.into_iter().enumerate() {
+            self.connect_devices(source, subpatch_id, WireType::Normal, i + 1, feedback);
+        }
+        for wire in outgoing.iter() {
+            self.connect_devices(
+                subpatch_id,
+                wire.to,
+                wire.wire_type,
+                wire.port,
+                wire.feedback,
+            );
+        }
+
+        self.select_device(subpatch_id);
+    }
+
     pub fn copy_selected_devices(&mut self) {
+        self.clipboard = self.selected_snippet();
+    }
+
+    // top-left corner of the selected devices' and comments' positions
+    fn selection_top_left(&self) -> Option<Vec2> {
+        let device_positions = self
+            .selected
+            .iter()
+            .filter_map(|id| self.device_position(*id));
+        let comment_positions = self
+            .comments
+            .iter()
+            .filter(|c| c.selected)
+            .map(|c| c.position);
+        device_positions.chain(comment_positions).reduce(Vec2::min)
+    }
+
+    /// Copies of the selected devices and comments, the wires between the
+    /// devices, their labels and the MIDI mappings. Mappings of BPM and the transport are
+    /// taken along too, but only from the top level patch.
+    pub fn selected_snippet(&self) -> Snippet {
+        let top_left = self.selection_top_left().unwrap_or(Vec2::ZERO);
+
         let mut devices = HashMap::new();
-        let mut top_left = Vec2::new(f32::INFINITY, f32::INFINITY);
         for dev_id in &self.selected {
-            if let Some(device) = self.devices.get(&dev_id) {
-                let pos = device.get_position();
-                if pos.x < top_left.x {
-                    top_left.x = pos.x;
-                }
-                if pos.y < top_left.y {
-                    top_left.y = pos.y;
-                }
+            if let Some(device) = self.devices.get(dev_id) {
                 devices.insert(*dev_id, device.clone_dyn());
             }
         }
 
-        // set device positions to be relative to bounding box top-left corner
+        // set positions to be relative to bounding box top-left corner
         for (_, device) in devices.iter_mut() {
             device.set_position(device.get_position() - top_left);
         }
+        let labels = self
+            .labels
+            .iter()
+            .filter(|(id, _)| devices.contains_key(id))
+            .map(|(id, label)| (*id, label.clone()))
+            .collect();
+        let comments = self
+            .comments
+            .iter()
+            .filter(|c| c.selected)
+            .map(|c| Comment {
+                position: c.position - top_left,
+                ..c.clone()
+            })
+            .collect();
 
         let mut edges = Vec::new();
         for edge in self.circuit.wires() {
@@ -219,11 +804,33 @@ impl Session {
             }
         }
 
-        self.clipboard = (devices, edges);
+        let mappings = if self.is_editing_subpatch() {
+            Vec::new()
+        } else {
+            self.midi_mappings
+                .iter()
+                .filter(|m| match m.target {
+                    LearnTarget::Parameter(id, _) => devices.contains_key(&id),
+                    _ => true,
+                })
+                .cloned()
+                .collect()
+        };
+
+        (devices, edges, labels, comments, mappings)
+    }
+
+    /// Adds copies of the selected devices next to the originals, and selects
+    /// the copies
+    pub fn duplicate_selected_devices(&mut self) {
+        if let Some(top_left) = self.selection_top_left() {
+            let snippet = self.selected_snippet();
+            self.paste_snippet(snippet, top_left + Vec2::splat(SNAP_GRID_SIZE * 2.0));
+        }
     }
 
     pub fn paste_clipboard(&mut self, position: Vec2) {
-        let (devices, edges) = &self.clipboard;
+        let (devices, edges, labels, comments, mappings) = &self.clipboard;
 
         let mut new_devices = HashMap::new();
         for (id, device) in devices.iter() {
@@ -231,6 +838,25 @@ impl Session {
         }
         let edges = edges.clone();
 
+        self.paste_snippet(
+            (
+                new_devices,
+                edges,
+                labels.clone(),
+                comments.clone(),
+                mappings.clone(),
+            ),
+            position,
+        );
+    }
+
+    /// Adds the contents of a snippet to the session, with the snippet's
+    /// top-left at `position`, and selects them. Its MIDI mappings are only
+    /// kept when pasting into the top level patch, and mappings of BPM and the
+    /// transport don't replace ones that are already there.
+    pub fn paste_snippet(&mut self, snippet: Snippet, position: Vec2) {
+        let (mut new_devices, edges, labels, comments, mappings) = snippet;
+
         let mut dev_id_map = HashMap::new();
         for (old_id, device) in new_devices.drain() {
             let new_id = self.add_device(device);
@@ -241,13 +867,111 @@ impl Session {
         for edge in edges.clone().iter() {
             let from = dev_id_map.get(&edge.from).unwrap();
             let to = dev_id_map.get(&edge.to).unwrap();
-            self.connect_devices(*from, *to, edge.wire_type);
+            self.connect_devices(*from, *to, edge.wire_type, edge.port, edge.feedback);
+        }
+
+        for (old_id, label) in labels {
+            if let Some(new_id) = dev_id_map.get(&old_id) {
+                self.labels.insert(*new_id, label);
+            }
         }
 
         self.clear_selection();
         for dev_id in dev_id_map.values() {
             self.select_device(*dev_id);
         }
+
+        for comment in comments {
+            self.comments.push(Comment {
+                position: comment.position + position,
+                selected: true,
+                ..comment
+            });
+        }
+
+        if self.is_editing_subpatch() {
+            return;
+        }
+        for mapping in mappings {
+            let target = match mapping.target {
+                LearnTarget::Parameter(id, name) => match dev_id_map.get(&id) {
+                    Some(new_id) => LearnTarget::Parameter(*new_id, name),
+                    None => continue,
+                },
+                target if self.midi_mappings.iter().any(|m| m.target == target) => continue,
+                target => target,
+            };
+            self.midi_mappings.push(MidiMapping { target, ..mapping });
+        }
+    }
+
+    /// Maps a MIDI control to a target, replacing any existing mapping for
+    /// that target. The mapping starts out covering the target's full range.
+    pub fn learn_mapping(&mut self, source: ControlSource, target: LearnTarget) {
+        let (min, max) = match target {
+            LearnTarget::Parameter(id, name) => {
+                let Some(param) = self
+                    .root_devices()
+                    .get(&id)
+                    .and_then(|d| d.parameters().into_iter().find(|p| p.name == name))
+                else {
+                    return;
+                };
+                (param.min, param.max)
+            }
+            LearnTarget::Bpm => MAPPED_BPM_RANGE,
+            LearnTarget::PlayPause | LearnTarget::Reset => (0.0, 1.0),
+        };
+
+        self.forget_mapping(target);
+        self.midi_mappings.push(MidiMapping {
+            source,
+            target,
+            min,
+            max,
+            last_value: 0,
+        });
+    }
+
+    pub fn forget_mapping(&mut self, target: LearnTarget) {
+        self.midi_mappings.retain(|m| m.target != target);
+    }
+
+    /// Applies a value received from a MIDI control to everything mapped to
+    /// it. Parameters and BPM are set directly, while the button targets that
+    /// were pressed are returned for the caller to act on.
+    pub fn apply_midi_control(&mut self, source: ControlSource, value: u8) -> Vec<LearnTarget> {
+        let mut pressed = Vec::new();
+
+        let root_devices = match self.parents.first_mut() {
+            Some(root) => &mut root.devices,
+            None => &mut self.devices,
+        };
+        for mapping in self.midi_mappings.iter_mut() {
+            if mapping.source != source {
+                continue;
+            }
+
+            match mapping.target {
+                LearnTarget::Parameter(id, name) => {
+                    if let Some(device) = root_devices.get_mut(&id) {
+                        device.set_parameter(name, mapping.scale(value));
+                    }
+                }
+                LearnTarget::Bpm => {
+                    self.update_ctx.bpm = mapping.scale(value).round().clamp(20.0, 777.0) as u32;
+                }
+                LearnTarget::PlayPause | LearnTarget::Reset => {
+                    if value >= 64 && mapping.last_value < 64 {
+                        pressed.push(mapping.target);
+                    }
+                }
+            }
+
+            mapping.last_value = value;
+        }
+
+        pressed
     }
 
     pub fn toggle_pause(&mut self) {
@@ -259,11 +983,20 @@ impl Session {
         self.update_ctx.free_clock = Duration::ZERO;
         self.update_ctx.last_update = Instant::now();
 
-        for dev in self.devices.values_mut() {
+        let parent_devices = self.parents.iter_mut().flat_map(|p| p.devices.values_mut());
+        for dev in self.devices.values_mut().chain(parent_devices) {
             dev.reset();
         }
     }
 
+    /// Stops the notes of every device, including ones in subpatches
+    pub fn release_notes(&mut self) {
+        let parent_devices = self.parents.iter_mut().flat_map(|p| p.devices.values_mut());
+        for dev in self.devices.values_mut().chain(parent_devices) {
+            dev.release();
+        }
+    }
+
     pub fn update(&mut self) {
         self.update_ctx.this_update = Instant::now();
 
@@ -275,37 +1008,101 @@ impl Session {
             self.update_ctx.beat_clock += beats_elapsed;
         }
 
-        let mut device_outputs: HashMap<DeviceId, bool> = HashMap::new();
-        for dev_id in self.circuit.devices() {
-            let inputs: Vec<bool> = self
-                .circuit
-                .incoming(*dev_id)
-                .filter_map(|wire| match wire.wire_type {
-                    WireType::Normal => device_outputs.get(&wire.from).copied(),
-                    WireType::Negated => device_outputs.get(&wire.from).copied().map(|x| !x),
-                })
-                .collect();
+        // the whole patch is evaluated from the top, even while a subpatch is
+        // open for editing. The contents of each open subpatch are then a level
+        // further down, in the next parent or the devices being edited.
+        let open_ids: Vec<DeviceId> = self.parents.iter().map(|p| p.subpatch).collect();
+        let mut levels = self
+            .parents
+            .iter_mut()
+            .map(|p| (&p.circuit, &mut p.devices))
+            .chain(std::iter::once((&self.circuit, &mut self.devices)));
+        let (root_circuit, root_devices) = levels.next().unwrap();
+        let mut open: Vec<OpenSubpatch> = open_ids
+            .into_iter()
+            .zip(levels)
+            .map(|(id, (circuit, devices))| OpenSubpatch {
+                id,
+                circuit,
+                devices,
+            })
+            .collect();
 
-            let dev = self.devices.get_mut(dev_id).unwrap();
-            if let Some(output) = dev.update(&mut self.update_ctx, inputs) {
-                device_outputs.insert(*dev_id, output);
-            }
-        }
+        self.prev_outputs = evaluate_open_circuit(
+            root_circuit,
+            root_devices,
+            &self.prev_outputs,
+            &mut self.update_ctx,
+            &mut open,
+        );
 
         self.update_ctx.last_update = self.update_ctx.this_update;
     }
 
+    pub fn has_root_device(&self, id: DeviceId) -> bool {
+        match self.parents.first() {
+            Some(root) => root.devices.contains_key(&id),
+            None => self.devices.contains_key(&id),
+        }
+    }
+
+    /// Outputs of the top level devices from the last update
+    pub fn root_outputs(&self) -> &HashMap<DeviceId, Signal> {
+        &self.prev_outputs
+    }
+
+    // outputs from the last update of the devices being edited, which are
+    // kept by the subpatch while one is open
+    fn current_outputs(&self) -> Option<&HashMap<DeviceId, Signal>> {
+        match self.parents.last() {
+            Some(parent) => parent
+                .devices
+                .get(&parent.subpatch)
+                .and_then(|d| d.as_subpatch())
+                .map(Subpatch::outputs),
+            None => Some(&self.prev_outputs),
+        }
+    }
+
     pub fn draw(&self, draw_ctx: &DrawContext) {
+        for comment in self.comments.iter() {
+            comment.draw(draw_ctx);
+        }
+
+        let outputs = self.current_outputs();
         for wire in self.circuit.wires() {
             let from_dev = self.devices.get(&wire.from).unwrap();
             let to_dev = self.devices.get(&wire.to).unwrap();
+
+            // boolean wires light up while they carry true, after negation
+            let value = outputs.and_then(|o| o.get(&wire.from));
+            let carries_true = from_dev.output_kind() == SignalKind::Bool
+                && value.is_some_and(|v| v.as_bool() != (wire.wire_type == WireType::Negated));
+            let color = if carries_true {
+                draw_ctx.colors.active
+            } else {
+                draw_ctx.colors.fg_1
+            };
+
             draw_wire_between_devices(
                 draw_ctx,
                 from_dev.as_ref(),
                 to_dev.as_ref(),
                 wire.wire_type,
-                draw_ctx.colors.fg_1,
+                wire.feedback,
+                color,
             );
+
+            // label wires going into aux inputs so they can be told apart
+            if let Some(name) = wire
+                .port
+                .checked_sub(1)
+                .and_then(|i| to_dev.aux_inputs().into_iter().nth(i))
+                .map(|input| input.name)
+            {
+                let label_pos = to_dev.closest_border_point(from_dev.get_position(), 6.0);
+                draw_port_label(draw_ctx, draw_ctx.world_to_viewport(label_pos), &name);
+            }
         }
 
         for (dev_id, device) in &self.devices {
@@ -315,9 +1112,92 @@ impl Session {
             device.draw(
                 draw_ctx,
                 draw_ctx.world_to_viewport(pos),
-                24.0,
+                24.0 * draw_ctx.scale,
                 self.selected.contains(dev_id),
             );
+
+            if let Some(label) = self.labels.get(dev_id).filter(|l| !l.is_empty()) {
+                // just below the bottom of the device's shape
+                let bottom = device.closest_border_point(pos + Vec2::Y, 4.0);
+                draw_device_label(draw_ctx, draw_ctx.world_to_viewport(bottom), label);
+            }
         }
     }
 }
+
+/// Updates every device in a circuit in topological order, feeding each one
+/// the outputs of the devices wired into it, and returns all of the outputs.
+/// Feedback wires read from `prev_outputs` instead, which should be what this
+/// returned for the same circuit on the previous update.
+pub fn evaluate_circuit(
+    circuit: &Dag,
+    devices: &mut HashMap<DeviceId, Box<dyn Device>>,
+    prev_outputs: &HashMap<DeviceId, Signal>,
+    ctx: &mut UpdateContext,
+) -> HashMap<DeviceId, Signal> {
+    evaluate_open_circuit(circuit, devices, prev_outputs, ctx, &mut [])
+}
+
+// a subpatch that's open for editing, along with its contents, which the
+// session keeps outside of it while it's open
+struct OpenSubpatch<'a> {
+    id: DeviceId,
+    circuit: &'a Dag,
+    devices: &'a mut HashMap<DeviceId, Box<dyn Device>>,
+}
+
+// like evaluate_circuit, for a circuit that may have a subpatch open in it.
+// `open` is the path of open subpatches down from this circuit.
+fn evaluate_open_circuit(
+    circuit: &Dag,
+    devices: &mut HashMap<DeviceId, Box<dyn Device>>,
+    prev_outputs: &HashMap<DeviceId, Signal>,
+    ctx: &mut UpdateContext,
+    open: &mut [OpenSubpatch],
+) -> HashMap<DeviceId, Signal> {
+    let mut device_outputs: HashMap<DeviceId, Signal> = HashMap::new();
+    for dev_id in circuit.devices() {
+        let wire_value = |wire: &Wire| {
+            let outputs = if wire.feedback {
+                prev_outputs
+            } else {
+                &device_outputs
+            };
+            match wire.wire_type {
+                WireType::Normal => outputs.get(&wire.from).copied(),
+                WireType::Negated => outputs.get(&wire.from).map(|x| Signal::Bool(!x.as_bool())),
+            }
+        };
+
+        let inputs: Vec<Signal> = circuit
+            .incoming(*dev_id)
+            .filter(|wire| wire.port == 0)
+            .filter_map(wire_value)
+            .collect();
+
+        let dev = devices.get_mut(dev_id).unwrap();
+        for port in 1..=dev.aux_inputs().len() {
+            let value = circuit
+                .incoming(*dev_id)
+                .find(|wire| wire.port == port)
+                .and_then(wire_value);
+            dev.set_aux_input(port, value);
+        }
+
+        let output = match (open.split_first_mut(), dev.as_subpatch_mut()) {
+            (Some((inner, rest)), Some(subpatch)) if inner.id == *dev_id => subpatch.update_with(
+                ctx,
+                Some((inner.circuit, &mut *inner.devices)),
+                |circuit, devices, prev_outputs, ctx| {
+                    evaluate_open_circuit(circuit, devices, prev_outputs, ctx, rest)
+                },
+            ),
+            _ => dev.update(ctx, inputs),
+        };
+        if let Some(output) = output {
+            device_outputs.insert(*dev_id, output);
+        }
+    }
+
+    device_outputs
+}